@@ -0,0 +1,94 @@
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+/// Records which bins each keyword was placed in by `top_k_bins`, after any choices were removed.
+///
+/// Saved next to the bin contents so a client can work out which bins to fetch for a keyword
+/// without re-running BM25.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BinIndex {
+    /// Keyword to the sorted indices of the bins it was placed in
+    keywords: HashMap<String, Vec<usize>>,
+    /// Keywords that didn't fit into any of their bins. A client has to fetch the whole stash for these
+    #[serde(default)]
+    stashed: BTreeSet<String>,
+}
+
+impl BinIndex {
+    /// Creates an empty index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the bins a keyword was placed in. Bins are sorted and deduplicated.
+    ///
+    /// # Arguments
+    /// * `keyword` - The keyword, as it appears in the alphabet
+    /// * `bins` - Indices of the bins the keyword's results were inserted into
+    pub fn insert(&mut self, keyword: String, mut bins: Vec<usize>) {
+        bins.sort_unstable();
+        bins.dedup();
+        self.keywords.insert(keyword, bins);
+    }
+
+    /// Returns the bins to fetch for a keyword, or an empty vector if the keyword was never placed
+    ///
+    /// # Arguments
+    /// * `keyword` - The keyword to look up. This is a token from the alphabet, so it should already be tokenized
+    pub fn lookup(&self, keyword: &str) -> Vec<usize> {
+        self.keywords.get(keyword).cloned().unwrap_or_default()
+    }
+
+    /// Records a keyword that went to the stash instead of a bin
+    pub fn insert_stashed(&mut self, keyword: String) {
+        self.keywords.remove(&keyword);
+        self.stashed.insert(keyword);
+    }
+
+    /// Whether a keyword is in the stash rather than a bin
+    pub fn is_stashed(&self, keyword: &str) -> bool {
+        self.stashed.contains(keyword)
+    }
+
+    /// The stashed keywords, sorted
+    pub fn stashed(&self) -> impl Iterator<Item = &String> {
+        self.stashed.iter()
+    }
+
+    /// Iterates over every keyword and its bins
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Vec<usize>)> {
+        self.keywords.iter()
+    }
+
+    /// The number of keywords in the index
+    pub fn len(&self) -> usize {
+        self.keywords.len()
+    }
+
+    /// Whether the index has no keywords
+    pub fn is_empty(&self) -> bool {
+        self.keywords.is_empty()
+    }
+
+    /// Writes the index to `filename` as serde JSON
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be written
+    pub fn save(&self, filename: &str) -> Result<()> {
+        let writer = BufWriter::new(File::create(filename)?);
+        serde_json::to_writer(writer, self)?;
+        Ok(())
+    }
+
+    /// Reads an index previously written by `save`
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be read or is not a valid index
+    pub fn load(filename: &str) -> Result<Self> {
+        let reader = BufReader::new(File::open(filename)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
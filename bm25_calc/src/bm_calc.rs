@@ -1,22 +1,31 @@
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::hash::{DefaultHasher, Hash, Hasher};
-use std::io::BufWriter;
-use crate::error::Result;
-use bm25::{DefaultTokenizer, Language, SearchEngine, SearchEngineBuilder, SearchResult, Tokenizer};
+use std::io::{BufReader, BufWriter};
+use crate::bin_index::BinIndex;
+use crate::database::PaddedDatabase;
+use crate::dataloader::Text;
+use crate::hashing::{sample_bins, BinHasher};
+use crate::error::{BM25Error, Result};
+use bm25::{SearchEngine, SearchEngineBuilder, SearchResult, Tokenizer};
 use indicatif::ProgressBar;
-use tracing::{debug, info, trace, warn};
+use rayon::prelude::*;
+use tracing::{debug, info, trace};
 use serde::{Deserialize, Serialize};
+use crate::placement::{BinState, PlacementKind};
+use crate::snapshot::PostingSnapshot;
+use crate::tokenizer::{ngram_counts, KeywordTokenizer, Keywords};
+use crate::vocabulary::Vocabulary;
 use crate::Config;
-use sha2::{Sha256, Digest};
 
+/// Wrapper used when serialising bins to disk
 #[derive(Serialize, Deserialize)]
 struct Data {
+    /// The bins, each holding document IDs
     sets: Vec<HashSet<u32>>
 }
 
 /// Metadata for easy displaying
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 #[allow(dead_code)]
 pub struct Metadata {
     /// Value of k used in top-k
@@ -25,102 +34,324 @@ pub struct Metadata {
     pub num_bins: usize,
     /// The number of choices for d-choice hashing
     pub d: usize,
+    /// The removal pipeline d-choice placement ran, or "-" if choices weren't removed
+    pub removal: String,
     /// The numbers of items removed
     pub removed_items: usize,
     ///The total number of items
     pub total_items: usize,
     ///Average number of items per bin
     pub average_load_per_bin: usize,
+    ///Number of items in the fullest bin
+    pub max_load: usize,
+    ///The keywords placed, by number of words: single words first, then bigrams and so on
+    pub keyword_ngrams: Vec<usize>,
     ///The number of keywords that actually had an overlap
     pub keywords_with_overlap: usize,
+    ///Keywords that didn't fit into any of their bins and went to the stash
+    pub stash_size: usize,
+    ///Entries per bin once padded, `Config::bin_capacity` or the max load when unset
+    pub capacity: usize,
+    ///Dummy entries added by padding, as a fraction of the real entries
+    pub padding_overhead: f64,
+    ///Fraction of the padded entries that are real document IDs
+    pub real_fraction: f64,
+    ///Number of times a keyword was moved out of a bin to make room (cuckoo placement)
+    pub evictions: usize,
+    ///Mean recall of the binned layout against plain top-k, if it was evaluated
+    pub recall: Option<f64>,
+    ///Fraction of retrieved documents that were in the keyword's top-k, if it was evaluated
+    pub precision: Option<f64>,
+    ///Documents retrieved that were not in the keyword's top-k, if it was evaluated
+    pub false_positives: Option<usize>,
 }
 
+impl Metadata {
+    /// Builds the metadata for a plain top-k run, where every keyword gets a bin of its own
+    ///
+    /// # Arguments
+    /// * `results` - Output of `top_k`
+    /// * `k` - Value of k used in top-k
+    pub fn from_top_k(results: &HashMap<String, HashSet<u32>>, k: usize) -> Self {
+        let total_items = results.values().map(|set| set.len()).sum();
+        let max_load = results.values().map(HashSet::len).max().unwrap_or(0);
+        Self {
+            num_bins: results.len(),
+            k,
+            d: 1,
+            removal: "-".to_string(),
+            removed_items: 0,
+            total_items,
+            average_load_per_bin: total_items / results.len().max(1),
+            max_load,
+            keyword_ngrams: ngram_counts(results.keys().map(String::as_str)),
+            keywords_with_overlap: 0,
+            stash_size: 0,
+            capacity: max_load,
+            padding_overhead: 0.0,
+            real_fraction: 0.0,
+            evictions: 0,
+            recall: None,
+            precision: None,
+            false_positives: None,
+        }
+        .with_padding()
+    }
 
-#[macro_export]
-macro_rules! default_tokenizer {
-    () => {
-        DefaultTokenizer::builder()
-            .language_mode(Language::English)
-            .normalization(true)
-            .stopwords(true)
-            .stemming(true)
-            .build()
-    };
+    /// Fills in the padding overhead and real fraction from the capacity, bin count and total items
+    fn with_padding(mut self) -> Self {
+        let padded_items = self.capacity * self.num_bins;
+        self.padding_overhead = padded_items.saturating_sub(self.total_items) as f64 / self.total_items.max(1) as f64;
+        self.real_fraction = self.total_items as f64 / padded_items.max(1) as f64;
+        self
+    }
 }
 
+/// Turns a run or experiment name into something safe to use in a file name. Letters, digits, `.`, `-` and
+/// `=` are kept and every run of other characters becomes a single `_`
+pub fn file_stem(name: &str) -> String {
+    let mut stem = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '=') {
+            stem.push(c);
+        } else if !stem.ends_with('_') {
+            stem.push('_');
+        }
+    }
+    stem.trim_matches('_').to_string()
+}
 
-fn save_hashsets(sets: &Vec<HashSet<u32>>, filename: &str) -> Result<()> {
+/// The path, without extension, that a bins run saves its `.data`, `.index`, `.padded` and `.db` files under.
+///
+/// It holds every setting that changes the layout, so runs that differ only in placement, removal, hash or
+/// capacity don't overwrite each other's files
+pub fn save_name(config: &Config) -> String {
+    let removal = match config.placement {
+        PlacementKind::DChoice => config.removal_pipeline().to_string(),
+        _ => "none".to_string(),
+    };
+    let capacity = config.bin_capacity.map_or_else(|| "none".to_string(), |capacity| capacity.to_string());
+    let description = format!(
+        "k={} d={} bins={} filter_k={} placement={} removal={} order={} capacity={} evictions={} seed={} hash={} ids={}",
+        config.k,
+        config.d,
+        config.max_bins,
+        config.filter_k,
+        config.placement,
+        removal,
+        config.order,
+        capacity,
+        config.max_evictions,
+        config.seed,
+        config.hash,
+        config.id_encoding
+    );
+    format!("saved/{}", file_stem(&description))
+}
+
+/// Writes bins to `filename` as serde JSON
+fn save_hashsets(sets: &[HashSet<u32>], filename: &str) -> Result<()> {
     let file = File::create(filename)?;
     let writer = BufWriter::new(file);
-    let data = Data { sets: sets.clone() };
+    let data = Data { sets: sets.to_vec() };
     serde_json::to_writer(writer, &data)?;
     Ok(())
 }
 
+/// Reads bins previously written when `Config::save_result` is set
+///
+/// # Arguments
+/// * `filename` - Path to the `.data` file
+///
+/// # Returns
+/// * `Result<Vec<HashSet<u32>>>` - The bins, in the order they were saved
+///
+/// # Errors
+/// Returns error if the file cannot be read or is not valid JSON
+pub fn load_hashsets(filename: &str) -> Result<Vec<HashSet<u32>>> {
+    let reader = BufReader::new(File::open(filename)?);
+    let data: Data = serde_json::from_reader(reader)?;
+    Ok(data.sets)
+}
+
 /// Gets the "alphabet" or the entire set of possible keywords. Returns a hashset of the keywords
 ///
 /// # Arguments
-/// * `corpus` - Vector of documents  (as strings)to tokenize
+/// * `corpus` - Documents to tokenize, each with its language if known
+/// * `tokenizer` - The tokenizer the search engine will use, so keywords match its terms
 ///
 /// # Returns
 /// * `Result<HashSet<String>>` - Set of unique tokens
-#[allow(clippy::ptr_arg)] // allow this for test cases
-pub fn get_alphabet(corpus: &Vec<String>) -> Result<HashSet<String>> {
-    let mut set = HashSet::new();
-    info!("Making alphabet");
+pub fn get_alphabet(corpus: &[Text], tokenizer: &KeywordTokenizer) -> Result<HashSet<String>> {
+    Ok(get_vocabulary(corpus, tokenizer)?.alphabet())
+}
 
-    let tokenizer = default_tokenizer!();
+/// Tokenizes the corpus and counts the documents each keyword appears in, for pruning the alphabet
+///
+/// # Arguments
+/// * `corpus` - Documents to tokenize, each with its language if known
+/// * `tokenizer` - The tokenizer the search engine will use, so keywords match its terms
+///
+/// # Returns
+/// * `Result<Vocabulary>` - Every keyword with its document frequency
+pub fn get_vocabulary(corpus: &[Text], tokenizer: &KeywordTokenizer) -> Result<Vocabulary> {
+    let mut vocabulary = Vocabulary::default();
+    info!("Making alphabet");
 
     info!("scanning alphabet");
     debug!("Bar init");
     let bar = ProgressBar::new(corpus.len() as u64);
     for document in corpus.iter() {
         bar.inc(1);
-        let tokens = tokenizer.tokenize(document);
-        set.extend(tokens);
+        let tokens = tokenizer.tokenize(&document.text, document.language.as_deref());
+        vocabulary.add_document(&tokens);
     }
     bar.finish();
-    Ok(set)
+    Ok(vocabulary)
 }
 
 /// Builds a search engine from a corpus of documents (See the BM25 crate girhubpage/documentation)
 ///
+/// Each document is tokenized with its own language and the engine indexes the keywords, so it is searched
+/// with keywords rather than text (see `tokenizer::Keywords`).
+///
+/// # Arguments
+/// * `corpus` - Documents to index, each with its language if known
+/// * `tokenizer` - The tokenizer the alphabet was built with, so the engine's terms are the alphabet's keywords
+///
+/// # Returns
+/// * `KeywordSearchEngine` - Search engine initialized and ready to search through the entire corpus
+pub fn build_search_engine(corpus: &[Text], tokenizer: &KeywordTokenizer) -> KeywordSearchEngine {
+    let documents: Vec<String> = corpus
+        .iter()
+        .map(|document| tokenizer.tokenize(&document.text, document.language.as_deref()).join(" "))
+        .collect();
+    SearchEngineBuilder::<u32, u32, Keywords>::with_tokenizer_and_corpus(Keywords, documents).build()
+}
+
+/// The BM25 crate's search engine over documents tokenized by a `KeywordTokenizer`
+pub type KeywordSearchEngine = SearchEngine<u32, u32, Keywords>;
+
+/// Anything that can answer a BM25 query over the corpus.
+///
+/// Implemented for the BM25 crate's `SearchEngine` and for `streaming::StreamingIndex`, which holds the
+/// same scores without keeping the document text. Searchers are shared between threads by `search_alphabet`.
+pub trait Searcher: Sync {
+    /// Returns up to `limit` documents matching `query`, highest score first. The query is keywords separated by
+    /// whitespace: a keyword from the alphabet, or a query tokenized with `KeywordTokenizer::tokenize`
+    fn search(&self, query: &str, limit: usize) -> Vec<SearchResult<u32>>;
+
+    /// The number of documents in the corpus. Their IDs run from 0 up to this
+    fn num_documents(&self) -> usize;
+}
+
+impl<T: Tokenizer + Sync> Searcher for SearchEngine<u32, u32, T> {
+    fn search(&self, query: &str, limit: usize) -> Vec<SearchResult<u32>> {
+        Self::search(self, query, limit)
+    }
+
+    fn num_documents(&self) -> usize {
+        self.iter().count()
+    }
+}
+
+/// Searches for the top k documents, breaking ties in BM25 score by document ID.
+///
+/// The BM25 crate returns documents with equal scores in hash order, so two searches for the same
+/// word can return different top-k sets. This asks for more results until the k-th score is strictly
+/// greater than the next one, then sorts on (score, ID).
+///
 /// # Arguments
-/// * `corpus` - Collection of documents that can be converted to Strings. Usually just passed in as a string.
+/// * `search_engine` - Search engine to query
+/// * `query` - The query, usually a single keyword
+/// * `k` - Number of results to return
 ///
 /// # Returns
-/// * `SearchEngine<u32>` - Search engine initialized and ready to search through the entire corpus
-pub fn build_search_engine(corpus: Vec<impl Into<String>>) -> SearchEngine<u32> {
-    SearchEngineBuilder::<u32>::with_corpus(Language::English, corpus).build()
+/// * `Vec<SearchResult<u32>>` - At most k results, highest score first
+pub fn search_top_k<S: Searcher + ?Sized>(search_engine: &S, query: &str, k: usize) -> Vec<SearchResult<u32>> {
+    if k == 0 {
+        return Vec::new();
+    }
+
+    let mut limit = k.saturating_mul(2);
+    loop {
+        let mut results = search_engine.search(query, limit);
+        let exhausted = results.len() < limit;
+
+        if exhausted || results[k - 1].score > results[k].score {
+            results.sort_by(|a, b| {
+                b.score
+                    .total_cmp(&a.score)
+                    .then(a.document.id.cmp(&b.document.id))
+            });
+            results.truncate(k);
+            return results;
+        }
+
+        limit = limit.saturating_mul(2);
+    }
 }
 
-/// Performs top-k search for each word in the alphabet and filters results. Doesn't do any choice hashing or anything speical, just returns top-k. Theoretic return size is O(k * alphabet), i.e. each bin has 10 full results in each bin
+/// Runs `search_top_k` for every word in the alphabet on the rayon thread pool.
+///
+/// Words are sorted first and results come back in that order, so callers see the same output
+/// whatever the number of threads. Use `rayon::ThreadPoolBuilder` to limit the threads used.
 ///
 /// # Arguments
-/// * `k` - Number of results to retrieve per word. the k in top-k
-/// * `search_engine` - Search engine to query (See Rust BM25 crate)
+/// * `search_engine` - Search engine to query
 /// * `alphabet` - The keyword space
+/// * `k` - Number of results per word
+///
+/// # Returns
+/// * `Vec<(String, Vec<SearchResult<u32>>)>` - Each word with its top-k results, sorted by word
+pub fn search_alphabet<S: Searcher + ?Sized>(
+    search_engine: &S,
+    alphabet: &HashSet<String>,
+    k: usize,
+) -> Vec<(String, Vec<SearchResult<u32>>)> {
+    let mut words: Vec<&String> = alphabet.iter().collect();
+    words.sort_unstable();
+
+    let bar = ProgressBar::new(words.len() as u64);
+    let results = words
+        .into_par_iter()
+        .map(|word| {
+            let search_results = search_top_k(search_engine, word, k);
+            bar.inc(1);
+            (word.clone(), search_results)
+        })
+        .collect();
+    bar.finish();
+
+    results
+}
+
+/// Performs top-k search for each word in the alphabet and filters results.
+///
+/// Doesn't do any choice hashing or anything speical, just returns top-k. Theoretic return size is O(k * alphabet), i.e. each bin has 10 full results in each bin
+///
+/// # Arguments
+/// * `k` - Number of results to retrieve per word. the k in top-k. Ties are broken by document ID (see `search_top_k`)
+/// * `snapshot` - Search results for every keyword in the alphabet, built with at least this k (see `PostingSnapshot`)
 /// * `filter_k` - Minimum number of results required to keep a word. I.e. if this is 2, then allr esults with a top-k of only 1 while be discarded
 ///
 /// # Returns
 /// * `HashMap<String, HashSet<u32>>` - Map of words to sets of matching document IDs. The ID matches the index in the corpus array (See BM25 crate)
+///
+/// # Errors
+/// Returns error if the snapshot holds fewer than k results per keyword
 #[allow(clippy::map_entry)] // allow this because debugging is easier when using insert
 pub fn top_k(
     k: usize,
-    search_engine: &SearchEngine<u32>,
-    alphabet: &HashSet<String>,
+    snapshot: &PostingSnapshot,
     filter_k: usize,
-) -> HashMap<String, HashSet<u32>> {
+) -> Result<HashMap<String, HashSet<u32>>> {
     let mut results = HashMap::new();
 
-    let bar = ProgressBar::new(alphabet.len() as u64);
-
     let mut counting_duplicates = HashMap::new();
     let mut num_items = 0;
 
-    for word in alphabet {
-        let search_results = search_engine.search(word, k);
-        bar.inc(1);
+    for (word, search_results) in snapshot.top_k(k)? {
         if search_results.len() < filter_k {
             // filter out low results
             continue;
@@ -128,22 +359,20 @@ pub fn top_k(
 
         for result in search_results {
             results
-                .entry(word.to_string())
+                .entry(word.clone())
                 .or_insert_with(HashSet::new)
-                .insert(result.document.id);
+                .insert(result.id);
             num_items += 1; // increment the total number of items in bins for logging
-            if counting_duplicates.contains_key(&result.document.id) {
+            if counting_duplicates.contains_key(&result.id) {
                 // if this item was already previously inserted, count it as a duplicate
-                *counting_duplicates.get_mut(&result.document.id).unwrap() += 1;
+                *counting_duplicates.get_mut(&result.id).unwrap() += 1;
             } else {
                 // if this is the first time we're seeing this document ID, insert it as a new item
-                counting_duplicates.insert(result.document.id, 0);
+                counting_duplicates.insert(result.id, 0);
             }
         }
     }
 
-    bar.finish();
-
     info!(
         "Top-K done without d-choice. Total number of duplicates: {}, total items in bins: {}",
         counting_duplicates.values().sum::<i32>(),
@@ -155,119 +384,69 @@ pub fn top_k(
         results.values().map(|set| set.len()).sum::<usize>() as f64 / results.len() as f64
     );
 
-    results
+    Ok(results)
 }
 
-/// Deterministic function that can generate a hash value from a string and number
+/// Returns the d bins a keyword can be placed in, one per hash choice. These are the bins a client
+/// fetches when it doesn't know which choices were kept.
 ///
-/// # Arguments
-/// * `s` - String to hash
-/// * `n` - Number to combine with string hash
+/// The bins are always distinct and every bin is equally likely (see `hashing::sample_bins`). If d is larger
+/// than the number of bins, every bin is returned.
 ///
-/// # Returns
-/// * `u64` - Combined hash value
-fn get_hash(s: &str, n: &usize) -> u64 {
-    let mut hasher = Sha256::new();
-    trace!("about to hash {} and {}", s, n);
-    hasher.update(s.as_bytes());
-    hasher.update(n.to_string().as_bytes());
-    let result = hasher.finalize();
-
-    // Convert first 8 bytes of SHA-256 hash to u64
-    let mut bytes = [0u8; 8];
-    bytes.copy_from_slice(&result[0..8]);
-    u64::from_be_bytes(bytes)
-}
-
-fn get_bins(word: &str,
-            d: &usize, max_bins: &u64,
-            document_ids: &HashSet<u32>,
-            orig_results: &Vec<Vec<u32>>,
-            search_results_len: &usize) -> Result<Vec<(usize, usize, usize)>> {
-    let mut bin_choices = Vec::with_capacity(d.clone());
-
-    // Try d different hash functions
-    for choice in 0..d.clone() {
-        let index: usize = (get_hash(word, &choice) % max_bins).try_into()?;
-
-        let mut overlap = orig_results[index].iter()
-            .filter(|&id| document_ids.contains(id))
-            .count();
-
-        // minus the original insertions
-        overlap -= document_ids.len();
-
-        if overlap >= usize::MAX - 111 {
-            panic!("Overlap was negative? {}", overlap)
-        }
-
-        let bin_size = orig_results[index].len();
-
-        trace!(
-                "Got index {}, overlap: {}, k: {}, bin size: {}",
-                index,
-                overlap,
-                search_results_len,
-                bin_size
-            );
-
-        bin_choices.push((index, bin_size, overlap));
-    }
-
-    Ok(bin_choices)
-
-}
-
-fn remove_min_overlap(mut bins: Vec<(usize, usize, usize)>, count: usize) -> Vec<(usize, usize, usize)> {
-    // Sort bins by overlap in ascending order (smallest overlap first)
-    bins.sort_by(|a, b| a.2.cmp(&b.2));
-
-    // Determine the number of bins to remove
-    let remove_count = count.min(bins.len()); // Prevent out-of-bounds
-
-
-    // Remove the first `remove_count` elements
-    bins.drain(0..remove_count);
-
-    bins
-}
-
-fn remove_max_load(mut bins: Vec<(usize, usize, usize)>, count: usize) -> Vec<(usize, usize, usize)> {
-    // Sort bins by overlap in descending order (largest overlap first)
-    bins.sort_by(|a, b| b.2.cmp(&a.2));
-
-    // Determine the number of bins to remove
-    let remove_count = count.min(bins.len()); // Prevent out-of-bounds
-
-    // Remove the first `remove_count` elements
-    bins.drain(0..remove_count);
-
-    bins
+/// # Arguments
+/// * `word` - The keyword
+/// * `d` - Number of hash choices
+/// * `max_bins` - Number of bins
+/// * `hasher` - The hash function, from `Config::hash`
+pub fn candidate_bins(word: &str, d: usize, max_bins: usize, hasher: &dyn BinHasher) -> Vec<usize> {
+    let sample = sample_bins(word, d, max_bins, hasher);
+    trace!(
+        "{} hashed to bins {:?} after {} rejected and {} repeated draws",
+        word,
+        sample.bins,
+        sample.rejected,
+        sample.repeated
+    );
+    sample.bins
 }
 
-/// Performs top-k search with d-choice hashing into multiple bins. Function is deterministic and should reveal the same results over each run.
+/// Performs top-k search with d-choice hashing into multiple bins.
+///
+/// Function is deterministic and should reveal the same results over each run.
 ///
 /// # Arguments
-/// * `k` - Number of results to retrieve per word. the k in top-k
-/// * `search_engine` - Search engine to query
-/// * `alphabet` - The keyword space
-/// * `d` - Number of hash choices per word
-/// * `max_bins` - Number of bins to distribute results into
-/// * `filter_k` - Minimum number of results required to keep a word
+/// * `snapshot` - Search results for every keyword in the alphabet, built with at least `config.k` (see `PostingSnapshot`)
+/// * `config` - k, d, the number of bins, filter_k and the placement strategy
 ///
 /// # Returns
 /// * `Vec<HashSet<u32>>` - Vector of bins containing document IDs
 ///
+/// # Errors
+/// Returns error if `max_bins` is 0, d-choice removal drops every choice, the snapshot holds fewer than k
+/// results per keyword, or the results can't be saved
+///
 /// # Notes
-/// Uses d-choice hashing to minimize collisions. For each word,
-/// tries d different hash functions and places results in bin
-/// with maximum overlap.
-#[allow(clippy::too_many_arguments)]
+/// Uses d-choice hashing to minimize collisions. For each word, `config.placement` picks which of
+/// its d hash choices the results go into (see `placement::PlacementStrategy`).
 pub fn top_k_bins(
-    search_engine: &SearchEngine<u32>,
-    alphabet: &HashSet<String>,
+    snapshot: &PostingSnapshot,
     config: Config
 ) -> Result<(Metadata, Vec<HashSet<u32>>)> {
+    let (metadata, results, _) = top_k_bins_with_index(snapshot, config)?;
+    Ok((metadata, results))
+}
+
+/// Same as `top_k_bins`, but also returns the bins each keyword was placed in.
+///
+/// When `save_result` is set the index is written next to the bins with an `.index` extension,
+/// and can be read back with `BinIndex::load`.
+///
+/// # Returns
+/// * `(Metadata, Vec<HashSet<u32>>, BinIndex)` - The metadata, the bins and the keyword to bin index
+pub fn top_k_bins_with_index(
+    snapshot: &PostingSnapshot,
+    config: Config
+) -> Result<(Metadata, Vec<HashSet<u32>>, BinIndex)> {
 
     // Get configurable elements
 
@@ -275,77 +454,60 @@ pub fn top_k_bins(
     let d = config.d;
     let max_bins = config.max_bins;
     let filter_k = config.filter_k;
-    let max_load_factor = config.max_load_factor;
     let save_result = config.save_result;
-    let min_overlap_factor = config.min_overlap_factor;
+    if max_bins == 0 {
+        return Err(BM25Error::Placement("there are no bins to fill".to_string()));
+    }
+    let removal = match config.placement {
+        PlacementKind::DChoice => {
+            let removal = config.removal_pipeline();
+            // Keywords with no choices left would go into no bin and not the stash either
+            if removal.removed() >= d {
+                return Err(BM25Error::Placement(format!("removing {removal} leaves none of the {d} choices")));
+            }
+            removal.to_string()
+        }
+        _ => "-".to_string(),
+    };
 
     info!(
-        "Starting top {} into {} bins with {} choice hashing. We will remove {} min overlap and {} max laod bins",
-        k, max_bins, d, min_overlap_factor, max_load_factor
+        "Starting top {} into {} bins with {} choice hashing and {} placement in {} order. Removing choices with {}",
+        k, max_bins, d, config.placement, config.order, removal
     );
 
-    let mut results = vec![HashSet::new(); max_bins];
-    let mut orig_results  = vec![Vec::new(); max_bins];
-    let bar = ProgressBar::new(alphabet.len() as u64);
     let mut total_overlap = 0;
     let mut keywords_with_overlap: usize = 0;
 
-    let mut archived_results: Vec<(String, Vec<SearchResult<u32>>)> = Vec::new();
-
-    for word in alphabet {
-        let search_results = search_engine.search(word, k);
+    // Convert search results to document IDs, skipping words with too few results
+    let mut archived_results: Vec<(&String, HashSet<u32>)> = snapshot
+        .top_k(k)?
+        .filter(|(_, search_results)| search_results.len() >= filter_k)
+        .map(|(word, search_results)| (word, search_results.iter().map(|result| result.id).collect()))
+        .collect();
+    config.order.arrange(&mut archived_results, config.seed);
 
-        // Convert search results to document IDs
-        let document_ids: HashSet<u32> = search_results
-            .iter()
-            .map(|result| result.document.id)
-            .collect();
-
-        // Skip words with too few results
-        if search_results.len() < filter_k {
-            bar.inc(1);
-            continue;
-        }
-        for choice in 0..d {
-            let index: usize = (get_hash(word, &choice) % (max_bins as u64)).try_into()?;
-            orig_results[index].extend(document_ids.clone());
-        }
-        bar.inc(1);
-        archived_results.push((word.clone(), search_results));
-
-    }
-    bar.finish();
-
-    let bar2 = ProgressBar::new(alphabet.len() as u64);
-
-    for (word, search_results) in archived_results {
-
-        // Convert search results to document IDs
-        let document_ids: HashSet<u32> = search_results
-            .iter()
-            .map(|result| result.document.id)
-            .collect();
-
-        let mut bin_choices = get_bins(&word, &d, &(max_bins as u64), &document_ids, &orig_results, &search_results.len())?;
-
-
-        bin_choices = remove_min_overlap(bin_choices, min_overlap_factor);
-        bin_choices = remove_max_load(bin_choices, max_load_factor);
-
-        let mut max_overlap = 0;
+    let mut state = BinState::new(
+        d,
+        max_bins,
+        config.hash.hasher(&config)?,
+        archived_results.iter().map(|(word, document_ids)| (word.as_str(), document_ids)),
+    );
+    let mut strategy = config.placement.strategy(&config);
 
-        for choice in bin_choices {
+    let bar2 = ProgressBar::new(archived_results.len() as u64);
 
-            if max_overlap < choice.2 {
-                max_overlap = choice.2;
-            }
+    for (word, document_ids) in &archived_results {
+        let placement = strategy.place(word, document_ids, &mut state)?;
 
-            results[choice.0].extend(document_ids.clone());
+        let inserted = state.insert_within(word, document_ids, &placement.bins, config.bin_capacity);
+        if inserted.is_empty() {
+            bar2.inc(1);
+            continue;
         }
 
-        total_overlap += max_overlap;
+        total_overlap += placement.overlap;
 
-        if max_overlap > 0 {
+        if placement.overlap > 0 {
             keywords_with_overlap += 1;
         }
         bar2.inc(1);
@@ -354,15 +516,37 @@ pub fn top_k_bins(
 
     bar2.finish();
 
+    // Keywords can be moved after they're placed, so the index is built from where they ended up
+    let stash_size = state.stash_len();
+    let evictions = state.evictions();
+    let stash = state.stashed_documents();
+    let (results, bin_index) = state.into_parts();
+
+    let total_items: usize = results.iter().map(HashSet::len).sum();
+    let max_load = results.iter().map(HashSet::len).max().unwrap_or(0);
+    let capacity = config.bin_capacity.unwrap_or(max_load);
+
     let metadata = Metadata {
         num_bins: max_bins,
         k,
         d,
+        removal,
         removed_items: total_overlap,
-        total_items: results.iter().map(|set| set.len()).sum(),
-        average_load_per_bin: (results.iter().map(|set| set.len()).sum::<usize>() / results.len()),
+        total_items,
+        average_load_per_bin: total_items / results.len().max(1),
+        max_load,
+        keyword_ngrams: ngram_counts(archived_results.iter().map(|(word, _)| word.as_str())),
         keywords_with_overlap,
-    };
+        stash_size,
+        capacity,
+        padding_overhead: 0.0,
+        real_fraction: 0.0,
+        evictions,
+        recall: None,
+        precision: None,
+        false_positives: None,
+    }
+    .with_padding();
 
 
 
@@ -371,16 +555,39 @@ pub fn top_k_bins(
         k, max_bins, d, total_overlap,  results.iter().map(|set| set.len()).sum::<usize>()
     );
 
+    if stash_size > 0 || evictions > 0 {
+        info!("{} keywords were stashed after {} evictions", stash_size, evictions);
+    }
+
+    info!(
+        "Padding every bin to {} entries adds {} dummy entries, {:.1}% of the database is real",
+        capacity,
+        (capacity * results.len()).saturating_sub(total_items),
+        metadata.real_fraction * 100.0
+    );
+
     info!(
         "The average number of items in bins is {}",
         results.iter().map(|set| set.len()).sum::<usize>() as f64 / results.len() as f64
     );
 
     if save_result {
-        save_hashsets(&results, &format!("saved/{}_k_choice_with_{}_chocies_{}_max_load_removed.data", k, d, max_load_factor))?;
+        let save_name = save_name(&config);
+        save_hashsets(&results, &format!("{save_name}.data"))?;
+        bin_index.save(&format!("{save_name}.index"))?;
+        let database = PaddedDatabase::pad(&results, capacity, stash)?;
+        database.save(&format!("{save_name}.padded"))?;
+        let header = database.write_binary(&format!("{save_name}.db"), config.id_encoding, snapshot.num_documents())?;
+        if let Some(document_ids) = snapshot.document_ids() {
+            document_ids.save(&format!("{save_name}.ids.json"))?;
+        }
+        debug!(
+            "Wrote {} records of {} bytes ({} bits per ID) to {}.db",
+            header.bin_count, header.record_width, header.bits_per_id, save_name
+        );
     }
 
-    Ok((metadata, results))
+    Ok((metadata, results, bin_index))
 }
 
 #[cfg(test)]
@@ -403,9 +610,11 @@ mod tests {
             .with_max_level(tracing::Level::DEBUG)
             .try_init();
 
-        let search = build_search_engine(CORPUS.iter().map(|&s| s.to_string()).collect());
-        let alphabet = get_alphabet(&CORPUS.iter().map(|&s| s.to_string()).collect()).unwrap();
-        let _top_k = top_k(10, &search, &alphabet, 4);
+        let tokenizer = KeywordTokenizer::default();
+        let search = build_search_engine(&CORPUS.map(Text::from), &tokenizer);
+        let alphabet = get_alphabet(&CORPUS.map(Text::from), &tokenizer).unwrap();
+        let snapshot = PostingSnapshot::build(&search, &alphabet, 10);
+        let _top_k = top_k(10, &snapshot, 4).unwrap();
     }
 
     #[test]
@@ -417,7 +626,7 @@ mod tests {
             "The sky blushed pink as the sun dipped below the horizon.",
         ];
 
-        let corpus = corpus_str.iter().map(|&s| s.to_string()).collect();
+        let corpus: Vec<Text> = corpus_str.iter().map(|&s| Text::from(s)).collect();
 
         let _ = tracing_subscriber::fmt()
             .with_test_writer()
@@ -429,7 +638,8 @@ mod tests {
         let max_bins = 4;
 
         info!("Testing overlap");
-        let alphabet = get_alphabet(&corpus).unwrap();
+        let tokenizer = KeywordTokenizer::default();
+        let alphabet = get_alphabet(&corpus, &tokenizer).unwrap();
 
         info!(
             "The total number of files is {} and the alphabet size is {}",
@@ -437,19 +647,151 @@ mod tests {
             alphabet.len()
         );
 
-        let search = build_search_engine(corpus);
-        let mut config = Config::default();
-        config.k = k;
-        config.d = d;
-        config.max_bins = max_bins;
-        config.min_overlap_factor = 9;
+        let search = build_search_engine(&corpus, &tokenizer);
+        let config = Config {
+            k,
+            d,
+            max_bins,
+            min_overlap_factor: 9,
+            max_load_factor: 0,
+            save_result: false,
+            ..Config::default()
+        };
 
-        let top_k_bins = top_k_bins(&search, &alphabet, config).unwrap();
+        let snapshot = PostingSnapshot::build(&search, &alphabet, k);
+        let top_k_bins = top_k_bins(&snapshot, config).unwrap();
 
         (0..max_bins).for_each(|i| {
-            let length = top_k_bins[i].len();
+            let length = top_k_bins.1[i].len();
             debug!("Length is {}", length);
             assert!(length == 0 || length == 4);
         });
     }
+
+    #[test]
+    fn lookup_finds_results() {
+        let corpus: Vec<Text> = CORPUS.iter().map(|&s| Text::from(s)).collect();
+        let tokenizer = KeywordTokenizer::default();
+        let alphabet = get_alphabet(&corpus, &tokenizer).unwrap();
+        let search = build_search_engine(&corpus, &tokenizer);
+        let config = Config {
+            d: 3,
+            max_bins: 8,
+            min_overlap_factor: 1,
+            max_load_factor: 0,
+            save_result: false,
+            ..Config::default()
+        };
+
+        let snapshot = PostingSnapshot::build(&search, &alphabet, config.k);
+        let no_bins = Config {
+            max_bins: 0,
+            ..config.clone()
+        };
+        assert!(top_k_bins_with_index(&snapshot, no_bins).is_err());
+        let no_choices = Config {
+            max_load_factor: 2,
+            ..config.clone()
+        };
+        assert!(top_k_bins_with_index(&snapshot, no_choices).is_err());
+        let (_, bins, index) = top_k_bins_with_index(&snapshot, config).unwrap();
+
+        let papaya = index.lookup("papaya");
+        assert!(!papaya.is_empty() && papaya.len() <= 2);
+        // "papayas" is in document 1 only
+        assert!(papaya.iter().all(|&bin| bins[bin].contains(&1)));
+        assert!(index.lookup("not-a-keyword").is_empty());
+    }
+
+    #[test]
+    fn same_bins_for_any_thread_count() {
+        let corpus: Vec<Text> = CORPUS.iter().map(|&s| Text::from(s)).collect();
+        let tokenizer = KeywordTokenizer::default();
+        let alphabet = get_alphabet(&corpus, &tokenizer).unwrap();
+        let search = build_search_engine(&corpus, &tokenizer);
+        let config = Config {
+            d: 3,
+            max_bins: 8,
+            filter_k: 1,
+            min_overlap_factor: 1,
+            save_result: false,
+            ..Config::default()
+        };
+
+        let run = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| {
+                    let snapshot = PostingSnapshot::build(&search, &alphabet, config.k);
+                    top_k_bins_with_index(&snapshot, config.clone()).unwrap()
+                })
+        };
+        let (single, single_bins, single_index) = run(1);
+        let (multi, multi_bins, multi_index) = run(4);
+
+        assert_eq!(single_bins, multi_bins);
+        assert_eq!(single_index, multi_index);
+        assert_eq!(
+            (single.total_items, single.max_load, single.removed_items, single.keywords_with_overlap),
+            (multi.total_items, multi.max_load, multi.removed_items, multi.keywords_with_overlap)
+        );
+    }
+
+    #[test]
+    fn phrase_keywords() {
+        let corpus: Vec<Text> = [
+            "I moved to New York last year.",
+            "York is a city in the north of England.",
+            "The new car is red.",
+            "New York has a big park.",
+        ]
+        .iter()
+        .map(|&s| Text::from(s))
+        .collect();
+        let tokenizer = KeywordTokenizer::new(&crate::tokenizer::TokenizerConfig {
+            ngrams: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        let alphabet = get_alphabet(&corpus, &tokenizer).unwrap();
+        assert!(alphabet.contains("new+york") && alphabet.contains("york"));
+
+        // The phrase only matches documents where its words are adjacent
+        let search = build_search_engine(&corpus, &tokenizer);
+        let mut ids: Vec<u32> = search_top_k(&search, "new+york", 10).iter().map(|r| r.document.id).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [0, 3]);
+
+        let config = Config {
+            k: 2,
+            d: 4,
+            max_bins: 8,
+            filter_k: 1,
+            save_result: false,
+            ..Config::default()
+        };
+        let snapshot = PostingSnapshot::build(&search, &alphabet, config.k);
+        let (metadata, _, index) = top_k_bins_with_index(&snapshot, config).unwrap();
+        assert_eq!(metadata.keyword_ngrams.len(), 2);
+        assert_eq!(metadata.keyword_ngrams.iter().sum::<usize>(), alphabet.len());
+        assert!(!index.lookup("new+york").is_empty());
+    }
+
+    #[test]
+    fn save_names_differ() {
+        let config = Config::default();
+        let name = save_name(&config);
+        assert!(name.starts_with("saved/k=10_d=4_") && !name.contains(' ') && !name.contains(':'), "{name}");
+        let variants = [
+            Config { placement: PlacementKind::Cuckoo, ..config.clone() },
+            Config { min_overlap_factor: 0, ..config.clone() },
+            Config { bin_capacity: Some(8), ..config.clone() },
+            Config { hash: crate::hashing::HashKind::XxHash, ..config },
+        ];
+        let mut names: HashSet<String> = variants.iter().map(save_name).collect();
+        names.insert(name);
+        assert_eq!(names.len(), 5);
+    }
 }
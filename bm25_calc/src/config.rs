@@ -0,0 +1,77 @@
+use crate::database::IdEncoding;
+use crate::hashing::HashKind;
+use crate::placement::{KeywordOrder, PlacementKind};
+use crate::ranking::RankingPipeline;
+use serde::{Deserialize, Serialize};
+
+/// Parameters for a single top-k or top-k bins run
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// K parameter for top-k
+    pub k: usize,
+    /// The number of choices for d-choice hashing
+    pub d: usize,
+    /// The number of bins to hash keywords into
+    pub max_bins: usize,
+    /// Keywords with fewer than this many results are discarded
+    pub filter_k: usize,
+    /// How many of the d choices to drop by largest load. Ignored when `removal` is set
+    pub max_load_factor: usize,
+    /// How many of the d choices to drop by smallest overlap. Ignored when `removal` is set
+    pub min_overlap_factor: usize,
+    /// Stages that drop d-choice candidates, e.g. `overlap:lowest:1,load:highest:1`
+    pub removal: Option<RankingPipeline>,
+    /// Whether to write the bins to `saved/`
+    pub save_result: bool,
+    /// How document IDs are stored in the saved binary database
+    pub id_encoding: IdEncoding,
+    /// How each keyword's bins are chosen from its d choices
+    pub placement: PlacementKind,
+    /// The order keywords are placed in
+    pub order: KeywordOrder,
+    /// Most documents a bin may hold, and the width bins are padded to. Keywords that fit into none of their
+    /// choices go to the stash. Unbounded when unset, except cuckoo placement falls back to the average load
+    pub bin_capacity: Option<usize>,
+    /// Longest chain of evictions cuckoo placement follows before stashing a keyword
+    pub max_evictions: usize,
+    /// Seed for strategies that make random choices and the random keyword order
+    pub seed: u64,
+    /// The hash function that maps a keyword's choices to bins
+    pub hash: HashKind,
+    /// Secret the keyed hashes derive their key from. A client needs the same key to find a keyword's bins
+    pub hash_key: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            k: 10,
+            d: 4,
+            max_bins: 1024,
+            filter_k: 1,
+            max_load_factor: 1,
+            min_overlap_factor: 1,
+            removal: None,
+            save_result: true,
+            id_encoding: IdEncoding::U32,
+            placement: PlacementKind::DChoice,
+            order: KeywordOrder::Alphabet,
+            bin_capacity: None,
+            max_evictions: 64,
+            seed: 0,
+            hash: HashKind::Sha256,
+            hash_key: None,
+        }
+    }
+}
+
+impl Config {
+    /// The removal pipeline `d-choice` placement runs. Falls back to dropping `min_overlap_factor` choices by
+    /// smallest overlap and then `max_load_factor` by largest load when `removal` is unset
+    pub fn removal_pipeline(&self) -> RankingPipeline {
+        self.removal
+            .clone()
+            .unwrap_or_else(|| RankingPipeline::from_factors(self.min_overlap_factor, self.max_load_factor))
+    }
+}
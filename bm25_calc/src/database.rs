@@ -0,0 +1,509 @@
+use crate::error::{BM25Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::str::FromStr;
+
+/// Fills the unused slots of a padded bin. Never a real document ID, since those are indices into the corpus
+pub const DUMMY_ID: u32 = u32::MAX;
+/// First bytes of a binary database file
+pub const MAGIC: &[u8; 8] = b"BM25PIR\0";
+/// Version of the binary layout written by `PaddedDatabase::write_binary`
+pub const FORMAT_VERSION: u32 = 1;
+/// Size of the binary header in bytes. Records start right after it, packed back to back with no padding
+pub const HEADER_LEN: usize = 64;
+
+/// How document IDs are stored in a binary database
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IdEncoding {
+    /// Every ID is a little-endian u32
+    #[default]
+    U32,
+    /// IDs are packed least significant bit first using just enough bits for any ID in the corpus, with dummies as
+    /// all ones. Each record starts on a byte boundary
+    BitPacked,
+}
+
+impl FromStr for IdEncoding {
+    type Err = BM25Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "u32" => Ok(Self::U32),
+            "bit-packed" => Ok(Self::BitPacked),
+            _ => Err(BM25Error::Database(format!("unknown ID encoding {s}"))),
+        }
+    }
+}
+
+impl fmt::Display for IdEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::U32 => write!(f, "u32"),
+            Self::BitPacked => write!(f, "bit-packed"),
+        }
+    }
+}
+
+impl IdEncoding {
+    /// Tag stored in the header
+    const fn tag(self) -> u32 {
+        match self {
+            Self::U32 => 0,
+            Self::BitPacked => 1,
+        }
+    }
+
+    /// The encoding with this header tag
+    fn from_tag(tag: u32) -> Result<Self> {
+        match tag {
+            0 => Ok(Self::U32),
+            1 => Ok(Self::BitPacked),
+            _ => Err(BM25Error::Database(format!("unknown ID encoding tag {tag}"))),
+        }
+    }
+}
+
+/// The fixed-size header at the start of a binary database. All fields are little-endian:
+///
+/// | Offset | Size | Field |
+/// |--------|------|-------|
+/// | 0      | 8    | `MAGIC` |
+/// | 8      | 4    | `FORMAT_VERSION` |
+/// | 12     | 4    | ID encoding, 0 for u32 and 1 for bit-packed |
+/// | 16     | 4    | Bits per ID |
+/// | 20     | 4    | Reserved, zero |
+/// | 24     | 8    | Bin count |
+/// | 32     | 8    | IDs per record (the capacity) |
+/// | 40     | 8    | Record width in bytes |
+/// | 48     | 16   | Reserved, zero |
+///
+/// Bin `i` is the `record_width` bytes starting at `HEADER_LEN + i * record_width`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DatabaseHeader {
+    /// How IDs are stored
+    pub encoding: IdEncoding,
+    /// Bits used for each ID, 32 for `IdEncoding::U32`
+    pub bits_per_id: u32,
+    /// Number of records
+    pub bin_count: u64,
+    /// IDs per record, real and dummy
+    pub capacity: u64,
+    /// Bytes per record
+    pub record_width: u64,
+}
+
+impl DatabaseHeader {
+    /// Lays the header out as described on the type
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[0..8].copy_from_slice(MAGIC);
+        bytes[8..12].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.encoding.tag().to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.bits_per_id.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.bin_count.to_le_bytes());
+        bytes[32..40].copy_from_slice(&self.capacity.to_le_bytes());
+        bytes[40..48].copy_from_slice(&self.record_width.to_le_bytes());
+        bytes
+    }
+
+    /// Reads a header written by `to_bytes`
+    ///
+    /// # Errors
+    /// Returns error if the bytes are too short, the magic or version don't match, or the fields are inconsistent
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid = |reason: &str| BM25Error::Database(format!("bad header: {reason}"));
+        if bytes.len() < HEADER_LEN {
+            return Err(invalid("file is shorter than the header"));
+        }
+        if &bytes[0..8] != MAGIC {
+            return Err(invalid("not a binary database"));
+        }
+        let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap_or_default());
+        let u64_at = |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap_or_default());
+
+        let version = u32_at(8);
+        if version != FORMAT_VERSION {
+            return Err(invalid(&format!("version {version} is not supported")));
+        }
+        let header = Self {
+            encoding: IdEncoding::from_tag(u32_at(12))?,
+            bits_per_id: u32_at(16),
+            bin_count: u64_at(24),
+            capacity: u64_at(32),
+            record_width: u64_at(40),
+        };
+        if header.bits_per_id == 0
+            || header.bits_per_id > 32
+            || (header.encoding == IdEncoding::U32 && header.bits_per_id != 32)
+        {
+            return Err(invalid(&format!("{} bits per ID", header.bits_per_id)));
+        }
+        let width = record_width(header.capacity, header.bits_per_id)
+            .ok_or_else(|| invalid(&format!("a capacity of {} overflows the record width", header.capacity)))?;
+        if header.record_width != width {
+            return Err(invalid("record width doesn't match the capacity"));
+        }
+        Ok(header)
+    }
+}
+
+/// Bytes needed for `capacity` IDs of `bits` bits each, or `None` if that doesn't fit in a u64
+const fn record_width(capacity: u64, bits: u32) -> Option<u64> {
+    match capacity.checked_mul(bits as u64) {
+        Some(total) => Some(total.div_ceil(8)),
+        None => None,
+    }
+}
+
+/// The bins of a `top_k_bins` run padded to a fixed width, as a PIR server needs every record to be the same length.
+///
+/// Each bin holds its document IDs in ascending order followed by `DUMMY_ID` up to the capacity. Keywords
+/// that didn't fit into any of their choices are kept in the stash with their documents, which a client
+/// fetches in full.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PaddedDatabase {
+    /// Entries per bin
+    capacity: usize,
+    /// Every bin, exactly `capacity` entries long
+    bins: Vec<Vec<u32>>,
+    /// Stashed keywords and their sorted document IDs
+    stash: BTreeMap<String, Vec<u32>>,
+}
+
+impl PaddedDatabase {
+    /// Pads every bin to `capacity` with `DUMMY_ID`
+    ///
+    /// # Arguments
+    /// * `bins` - The bins from `top_k_bins`
+    /// * `capacity` - Entries per bin, at least the size of the fullest bin
+    /// * `stash` - Keywords that went to the overflow stash, with their documents
+    ///
+    /// # Errors
+    /// Returns error if a bin holds more than `capacity` documents
+    pub fn pad(bins: &[HashSet<u32>], capacity: usize, stash: BTreeMap<String, Vec<u32>>) -> Result<Self> {
+        let bins = bins
+            .iter()
+            .enumerate()
+            .map(|(index, bin)| {
+                if bin.len() > capacity {
+                    return Err(BM25Error::Database(format!(
+                        "bin {index} holds {} documents but the capacity is {capacity}",
+                        bin.len()
+                    )));
+                }
+                let mut padded: Vec<u32> = bin.iter().copied().collect();
+                padded.sort_unstable();
+                padded.resize(capacity, DUMMY_ID);
+                Ok(padded)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let stash = stash
+            .into_iter()
+            .map(|(keyword, mut documents)| {
+                documents.sort_unstable();
+                (keyword, documents)
+            })
+            .collect();
+
+        Ok(Self { capacity, bins, stash })
+    }
+
+    /// Entries per bin
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The padded bins
+    pub fn bins(&self) -> &[Vec<u32>] {
+        &self.bins
+    }
+
+    /// The stashed keywords and their documents
+    pub const fn stash(&self) -> &BTreeMap<String, Vec<u32>> {
+        &self.stash
+    }
+
+    /// The number of real document IDs across all bins
+    pub fn real_entries(&self) -> usize {
+        self.bins.iter().flatten().filter(|&&id| id != DUMMY_ID).count()
+    }
+
+    /// The number of dummy entries added across all bins
+    pub fn padding(&self) -> usize {
+        self.bins.len() * self.capacity - self.real_entries()
+    }
+
+    /// Writes the bins as a flat binary file: a `DatabaseHeader`, then one fixed-width record per bin in bin
+    /// order, each holding its sorted IDs followed by dummies. The stash is not included
+    ///
+    /// # Arguments
+    /// * `filename` - Where to write the database
+    /// * `encoding` - How to store IDs
+    /// * `num_documents` - The number of documents in the corpus. Bit-packed IDs are sized for it rather than for
+    ///   the IDs that happen to be in the bins, so every database of a corpus has the same width
+    ///
+    /// # Returns
+    /// * `Result<DatabaseHeader>` - The header that was written
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be written, a bin holds an ID outside the corpus, or the corpus is too
+    /// large for 32-bit IDs
+    pub fn write_binary(&self, filename: &str, encoding: IdEncoding, num_documents: usize) -> Result<DatabaseHeader> {
+        let count = u64::try_from(num_documents)?;
+        if let Some(&id) = self.bins.iter().flatten().find(|&&id| id != DUMMY_ID && u64::from(id) >= count) {
+            return Err(BM25Error::Database(format!(
+                "document {id} is outside a corpus of {num_documents} documents"
+            )));
+        }
+        let bits_per_id = match encoding {
+            IdEncoding::U32 => 32,
+            // Enough bits that every ID in the corpus is below the all-ones dummy
+            IdEncoding::BitPacked => count.checked_ilog2().map_or(1, |log| log + 1),
+        };
+        if bits_per_id > 32 {
+            return Err(BM25Error::Database(format!(
+                "{num_documents} documents don't fit in 32-bit IDs"
+            )));
+        }
+        let capacity = u64::try_from(self.capacity)?;
+        let header = DatabaseHeader {
+            encoding,
+            bits_per_id,
+            bin_count: u64::try_from(self.bins.len())?,
+            capacity,
+            record_width: record_width(capacity, bits_per_id)
+                .ok_or_else(|| BM25Error::Database(format!("a capacity of {capacity} overflows the record width")))?,
+        };
+
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_all(&header.to_bytes())?;
+        let mut record = Vec::with_capacity(usize::try_from(header.record_width)?);
+        for bin in &self.bins {
+            record.clear();
+            match encoding {
+                IdEncoding::U32 => record.extend(bin.iter().flat_map(|id| id.to_le_bytes())),
+                IdEncoding::BitPacked => pack(bin, bits_per_id, &mut record),
+            }
+            writer.write_all(&record)?;
+        }
+        writer.flush()?;
+        Ok(header)
+    }
+
+    /// Writes the database to `filename` as serde JSON
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be written
+    pub fn save(&self, filename: &str) -> Result<()> {
+        let writer = BufWriter::new(File::create(filename)?);
+        serde_json::to_writer(writer, self)?;
+        Ok(())
+    }
+
+    /// Reads a database previously written by `save`
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be read or is not a valid database
+    pub fn load(filename: &str) -> Result<Self> {
+        let reader = BufReader::new(File::open(filename)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+/// Packs IDs least significant bit first, mapping `DUMMY_ID` to all ones
+fn pack(ids: &[u32], bits: u32, out: &mut Vec<u8>) {
+    let dummy = u64::MAX >> (64 - bits);
+    let mut buffer: u64 = 0;
+    let mut filled = 0;
+    for &id in ids {
+        let value = if id == DUMMY_ID { dummy } else { u64::from(id) };
+        buffer |= value << filled;
+        filled += bits;
+        while filled >= 8 {
+            out.push(buffer as u8);
+            buffer >>= 8;
+            filled -= 8;
+        }
+    }
+    if filled > 0 {
+        out.push(buffer as u8);
+    }
+}
+
+/// A binary database written by `PaddedDatabase::write_binary`, for reading records back.
+///
+/// Works over any bytes, so a server can wrap a memory-mapped file with `from_bytes` without copying it, while
+/// `open` reads the file into memory for checking and tooling.
+#[derive(Clone, Debug)]
+pub struct BinaryDatabase<B = Vec<u8>> {
+    /// The parsed header
+    header: DatabaseHeader,
+    /// The whole file, header included
+    bytes: B,
+}
+
+impl BinaryDatabase {
+    /// Reads a binary database from disk
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be read or is not a valid database
+    pub fn open(filename: &str) -> Result<Self> {
+        Self::from_bytes(fs::read(filename)?)
+    }
+}
+
+impl<B: AsRef<[u8]>> BinaryDatabase<B> {
+    /// Parses a binary database held in memory, owned or borrowed
+    ///
+    /// # Errors
+    /// Returns error if the header is invalid or the records don't match it
+    pub fn from_bytes(bytes: B) -> Result<Self> {
+        let header = DatabaseHeader::from_bytes(bytes.as_ref())?;
+        let expected = header
+            .bin_count
+            .checked_mul(header.record_width)
+            .and_then(|records| records.checked_add(HEADER_LEN as u64));
+        let len = bytes.as_ref().len();
+        if expected != Some(u64::try_from(len)?) {
+            return Err(BM25Error::Database(format!(
+                "expected {} records of {} bytes but the file is {} bytes",
+                header.bin_count, header.record_width, len
+            )));
+        }
+        Ok(Self { header, bytes })
+    }
+
+    /// The header
+    pub const fn header(&self) -> &DatabaseHeader {
+        &self.header
+    }
+
+    /// The raw bytes of a record
+    ///
+    /// # Errors
+    /// Returns error if the bin is out of range
+    pub fn record_bytes(&self, bin: usize) -> Result<&[u8]> {
+        if u64::try_from(bin)? >= self.header.bin_count {
+            return Err(BM25Error::Database(format!(
+                "bin {bin} is out of range for {} bins",
+                self.header.bin_count
+            )));
+        }
+        let width = usize::try_from(self.header.record_width)?;
+        let start = HEADER_LEN + bin * width;
+        Ok(&self.bytes.as_ref()[start..start + width])
+    }
+
+    /// Decodes a record into its IDs, dummies included as `DUMMY_ID`
+    ///
+    /// # Errors
+    /// Returns error if the bin is out of range
+    pub fn record(&self, bin: usize) -> Result<Vec<u32>> {
+        let bytes = self.record_bytes(bin)?;
+        let capacity = usize::try_from(self.header.capacity)?;
+        Ok(match self.header.encoding {
+            IdEncoding::U32 => bytes
+                .chunks_exact(4)
+                .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                .collect(),
+            IdEncoding::BitPacked => unpack(bytes, self.header.bits_per_id, capacity),
+        })
+    }
+
+    /// Decodes a record into its real IDs only
+    ///
+    /// # Errors
+    /// Returns error if the bin is out of range
+    pub fn documents(&self, bin: usize) -> Result<Vec<u32>> {
+        let mut ids = self.record(bin)?;
+        ids.retain(|&id| id != DUMMY_ID);
+        Ok(ids)
+    }
+}
+
+/// Reverses `pack`
+fn unpack(bytes: &[u8], bits: u32, count: usize) -> Vec<u32> {
+    let dummy = u64::MAX >> (64 - bits);
+    let mut ids = Vec::with_capacity(count);
+    let mut buffer: u64 = 0;
+    let mut filled = 0;
+    let mut bytes = bytes.iter();
+    while ids.len() < count {
+        while filled < bits {
+            buffer |= u64::from(bytes.next().copied().unwrap_or(0)) << filled;
+            filled += 8;
+        }
+        let value = buffer & dummy;
+        buffer >>= bits;
+        filled -= bits;
+        ids.push(if value == dummy { DUMMY_ID } else { value as u32 });
+    }
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pads_to_capacity() {
+        let bins = vec![HashSet::from([3, 1]), HashSet::new(), HashSet::from([7, 2, 5])];
+        let stash = BTreeMap::from([("papaya".to_string(), vec![9, 4])]);
+        let database = PaddedDatabase::pad(&bins, 3, stash).unwrap();
+
+        assert_eq!(database.bins()[0], vec![1, 3, DUMMY_ID]);
+        assert_eq!(database.bins()[1], vec![DUMMY_ID; 3]);
+        assert_eq!(database.bins()[2], vec![2, 5, 7]);
+        assert_eq!(database.stash()["papaya"], vec![4, 9]);
+        assert_eq!(database.real_entries(), 5);
+        assert_eq!(database.padding(), 4);
+
+        assert!(PaddedDatabase::pad(&bins, 2, BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let bins = vec![HashSet::from([3, 1000, 1]), HashSet::new(), HashSet::from([7, 2])];
+        let database = PaddedDatabase::pad(&bins, 4, BTreeMap::new()).unwrap();
+        let path = std::env::temp_dir().join("bm25_calc_database.bin");
+        let path = path.to_str().unwrap();
+
+        for (encoding, bits, width) in [(IdEncoding::U32, 32, 16), (IdEncoding::BitPacked, 11, 6)] {
+            let header = database.write_binary(path, encoding, 2000).unwrap();
+            assert_eq!((header.bits_per_id, header.record_width), (bits, width));
+
+            let read = BinaryDatabase::open(path).unwrap();
+            assert_eq!(read.header(), &header);
+            for (bin, padded) in database.bins().iter().enumerate() {
+                assert_eq!(&read.record(bin).unwrap(), padded);
+            }
+            assert_eq!(read.documents(0).unwrap(), vec![1, 3, 1000]);
+            assert!(read.record(3).is_err());
+        }
+
+        // The width comes from the corpus, not the largest ID that made it into the bins
+        let header = database.write_binary(path, IdEncoding::BitPacked, 1024).unwrap();
+        assert_eq!(header.bits_per_id, 11);
+        assert!(database.write_binary(path, IdEncoding::BitPacked, 1000).is_err());
+
+        // A borrowed slice, as a server's mapping would be, reads the same as the owned file
+        let mut bytes = fs::read(path).unwrap();
+        let borrowed = BinaryDatabase::from_bytes(bytes.as_slice()).unwrap();
+        assert_eq!(borrowed.header(), &header);
+        assert_eq!(borrowed.documents(2).unwrap(), vec![2, 7]);
+        assert!(BinaryDatabase::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        bytes.pop();
+        assert!(BinaryDatabase::from_bytes(bytes).is_err());
+
+        // A capacity whose record width overflows is rejected rather than wrapping
+        let mut bytes = fs::read(path).unwrap();
+        bytes[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(DatabaseHeader::from_bytes(&bytes).is_err());
+    }
+}
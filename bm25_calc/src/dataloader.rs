@@ -1,29 +1,455 @@
-use crate::error::Result;
-use regex::Regex;
+use crate::document_ids::DocumentIds;
+use crate::error::{BM25Error, Result};
+use crate::preprocess::Preprocessor;
 use serde_json::Value;
 use std::{
-    fs::File,
-    io::{BufRead as _, BufReader},
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead as _, BufReader, Read},
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
-/// Removes all numeric values from a string
-///
-/// # Arguments
-/// * `input` - String to process
+/// Joiner used between multiple keys when none is given
+pub const DEFAULT_JOINER: &str = " ";
+
+/// The file formats a corpus can be read from
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CorpusFormat {
+    /// JSON lines, one document per line with the text under `key`
+    Jsonl,
+    /// Comma separated values with a header row, the text is in the column named `key`
+    Csv,
+    /// Tab separated values with a header row, the text is in the column named `key`
+    Tsv,
+    /// A directory of plain-text files, one document per file
+    TextDir,
+    /// A single JSON array of objects with the text under `key`
+    JsonArray,
+    /// MS MARCO / BEIR style `corpus.jsonl`, where each line has a `title` and a `text` field
+    Beir,
+}
+
+impl FromStr for CorpusFormat {
+    type Err = BM25Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "jsonl" | "ndjson" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            "tsv" => Ok(Self::Tsv),
+            "text-dir" | "dir" => Ok(Self::TextDir),
+            "json" | "json-array" => Ok(Self::JsonArray),
+            "beir" | "msmarco" => Ok(Self::Beir),
+            _ => Err(BM25Error::Corpus(format!("unknown corpus format {s}"))),
+        }
+    }
+}
+
+impl fmt::Display for CorpusFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Jsonl => "jsonl",
+            Self::Csv => "csv",
+            Self::Tsv => "tsv",
+            Self::TextDir => "text-dir",
+            Self::JsonArray => "json-array",
+            Self::Beir => "beir",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl CorpusFormat {
+    /// Guesses the format from the path. Directories are read as plain text, `corpus.jsonl` as BEIR,
+    /// and everything else by extension.
+    ///
+    /// # Errors
+    /// Returns error if the extension is not one we know how to read
+    pub fn detect(path: &str) -> Result<Self> {
+        let path = Path::new(path);
+        if path.is_dir() {
+            return Ok(Self::TextDir);
+        }
+        if path.file_name().is_some_and(|name| name == "corpus.jsonl") {
+            return Ok(Self::Beir);
+        }
+
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        match extension.to_lowercase().as_str() {
+            "json" => Ok(Self::JsonArray),
+            "" => Err(BM25Error::Corpus(format!(
+                "can't detect the format of {}, pass one explicitly",
+                path.display()
+            ))),
+            extension => extension.parse(),
+        }
+    }
+}
+
+/// A single field to read text from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldSpec {
+    /// The key as given, used as the column name for CSV/TSV
+    pub name: String,
+    /// JSON Pointer to the field, e.g. `/article/body`
+    pub pointer: String,
+    /// How many times the field's text is repeated, which weights it in BM25
+    pub weight: usize,
+}
+
+impl FromStr for FieldSpec {
+    type Err = BM25Error;
+
+    /// Parses `path` or `path^weight`. Paths starting with `/` are JSON Pointers, anything else is a
+    /// dotted path like `article.body`
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, weight) = match s.rsplit_once('^') {
+            Some((name, weight)) => (
+                name,
+                weight
+                    .parse()
+                    .ok()
+                    .filter(|&weight| weight > 0)
+                    .ok_or_else(|| BM25Error::Corpus(format!("invalid field weight in {s}")))?,
+            ),
+            None => (s, 1),
+        };
+
+        if name.is_empty() {
+            return Err(BM25Error::Corpus("empty key".to_string()));
+        }
+
+        let pointer = if name.starts_with('/') {
+            name.to_string()
+        } else {
+            // Escape each segment as RFC 6901 requires, so keys containing '/' or '~' still work
+            name.split('.')
+                .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+                .collect()
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            pointer,
+            weight,
+        })
+    }
+}
+
+/// The fields to read from each document and how to join them.
 ///
-/// # Returns
-/// * `String` - Input with all numbers removed
-#[allow(unused)]
-fn remove_numbers(input: &str) -> String {
-    let re = Regex::new(r"\d+[,\d]*\.?\d*").unwrap();
-    re.replace_all(input, "").to_string()
+/// Parsed from a comma separated list of fields, e.g. `title^2,article.body` reads the title twice and
+/// the nested body once. Missing fields are skipped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeySelector {
+    /// The fields, in the order their text is joined
+    pub fields: Vec<FieldSpec>,
+    /// String placed between fields (and between repeats of a weighted field)
+    pub joiner: String,
+    /// The field holding the document's language, which is kept next to its text
+    pub language: Option<FieldSpec>,
+}
+
+impl KeySelector {
+    /// Parses a key specification
+    ///
+    /// # Arguments
+    /// * `key` - Comma separated fields, each a JSON Pointer or dotted path with an optional `^weight`
+    /// * `joiner` - String placed between fields
+    ///
+    /// # Errors
+    /// Returns error if a field is empty or has an invalid weight
+    pub fn parse(key: &str, joiner: &str) -> Result<Self> {
+        Ok(Self {
+            fields: key.split(',').map(|field| field.trim().parse()).collect::<Result<_>>()?,
+            joiner: joiner.to_string(),
+            language: None,
+        })
+    }
+
+    /// Joins the selected text, repeating each part by its weight
+    fn join(&self, parts: impl Iterator<Item = (String, usize)>) -> String {
+        parts
+            .filter(|(text, _)| !text.is_empty())
+            .flat_map(|(text, weight)| std::iter::repeat_n(text, weight))
+            .collect::<Vec<_>>()
+            .join(&self.joiner)
+    }
+
+    /// Selects the text and language from a parsed JSON document
+    fn select_json(&self, json_val: &Value) -> Text {
+        let text = self.join(self.fields.iter().map(|field| {
+            let text = json_val.pointer(&field.pointer).map(value_text).unwrap_or_default();
+            (text, field.weight)
+        }));
+        let language = self.language.as_ref().and_then(|field| json_val.pointer(&field.pointer)?.as_str());
+        Text::new(text, language)
+    }
+
+    /// Selects the text and language from a CSV/TSV record, using the field names as column headers
+    ///
+    /// # Arguments
+    /// * `columns` - The column of each field
+    /// * `language_column` - The column of the language field, if there is one
+    /// * `record` - The record
+    fn select_record(&self, columns: &[usize], language_column: Option<usize>, record: &csv::StringRecord) -> Text {
+        let text = self.join(self.fields.iter().zip(columns).map(|(field, &column)| {
+            (record.get(column).unwrap_or_default().to_string(), field.weight)
+        }));
+        Text::new(text, language_column.and_then(|column| record.get(column)))
+    }
+}
+
+/// Where to read a corpus from and how to read it. Every format produces the same list of documents,
+/// ready for `bm_calc::get_alphabet` and `bm_calc::build_search_engine`.
+#[derive(Clone, Debug)]
+pub struct CorpusSource {
+    /// Path to the corpus file or directory
+    pub path: String,
+    /// The format of the corpus
+    pub format: CorpusFormat,
+    /// The JSON key(s) or CSV column(s) holding the text, see `KeySelector`. Not used for text directories or BEIR corpora
+    pub key: String,
+    /// String placed between the text of multiple keys
+    pub joiner: String,
+    /// The JSON key (dotted path or JSON Pointer) or CSV column holding each document's external ID.
+    /// BEIR corpora default to `_id` and text directories always use the file name
+    pub id_key: Option<String>,
+    /// The JSON key or CSV column holding each document's language (a name or ISO 639-1 code), for a
+    /// multilingual tokenizer. Not used for text directories or BEIR corpora
+    pub lang_key: Option<String>,
+    /// Cleaning applied to each document's text as it is read. Does nothing by default
+    pub preprocessor: Preprocessor,
+}
+
+/// Counts the bytes read from a corpus's files, so progress can be shown against `CorpusSource::size_on_disk`.
+/// Clones share the count
+#[derive(Clone, Debug, Default)]
+pub struct BytesRead(Arc<AtomicU64>);
+
+impl BytesRead {
+    /// The number of bytes read so far
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Opens a file whose reads are added to the count
+    fn open(&self, path: impl AsRef<Path>) -> Result<CountingReader> {
+        Ok(CountingReader {
+            file: File::open(path)?,
+            bytes_read: self.clone(),
+        })
+    }
+}
+
+/// A file that adds every byte read from it to a `BytesRead`
+struct CountingReader {
+    /// The file being read
+    file: File,
+    /// Where the bytes are counted
+    bytes_read: BytesRead,
+}
+
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.file.read(buf)?;
+        self.bytes_read.0.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// A document's text, with the language it is written in if the source gives one
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Text {
+    /// The text
+    pub text: String,
+    /// The language's name or ISO 639-1 code, as `tokenizer::parse_language` reads it
+    pub language: Option<String>,
+}
+
+impl Text {
+    /// Creates a text. An empty language is the same as none
+    pub fn new(text: String, language: Option<&str>) -> Self {
+        Self {
+            text,
+            language: language.filter(|language| !language.is_empty()).map(str::to_string),
+        }
+    }
+}
+
+impl From<String> for Text {
+    fn from(text: String) -> Self {
+        Self { text, language: None }
+    }
+}
+
+impl From<&str> for Text {
+    fn from(text: &str) -> Self {
+        text.to_string().into()
+    }
+}
+
+/// A document's text and language, with its external ID if the source has one
+pub type SourceDocument = (Text, Option<String>);
+
+/// Iterator over the documents in a source, read lazily
+pub type DocumentIter = Box<dyn Iterator<Item = Result<SourceDocument>>>;
+
+impl CorpusSource {
+    /// Creates a source, detecting the format from the path if one isn't given
+    ///
+    /// # Arguments
+    /// * `path` - Path to the corpus file or directory
+    /// * `key` - The JSON key(s) or CSV column(s) holding the text, see `KeySelector`
+    /// * `format` - The format, or `None` to detect it with `CorpusFormat::detect`
+    ///
+    /// # Errors
+    /// Returns error if no format is given and it can't be detected
+    pub fn new(path: &str, key: &str, format: Option<CorpusFormat>) -> Result<Self> {
+        let format = match format {
+            Some(format) => format,
+            None => CorpusFormat::detect(path)?,
+        };
+        Ok(Self {
+            path: path.to_string(),
+            format,
+            key: key.to_string(),
+            joiner: DEFAULT_JOINER.to_string(),
+            id_key: None,
+            lang_key: None,
+            preprocessor: Preprocessor::default(),
+        })
+    }
+
+    /// Reads every document in the corpus
+    ///
+    /// # Returns
+    /// * `Result<Vec<Text>>` - Every document's text and language, in file order
+    ///
+    /// # Errors
+    /// Returns error if the key is invalid or the corpus cannot be read or is malformed
+    pub fn load(&self) -> Result<Vec<Text>> {
+        Ok(self.load_with_ids()?.0)
+    }
+
+    /// Reads every document in the corpus along with its external ID.
+    ///
+    /// The position of a document in the returned vector is the `u32` ID the search engine gives it,
+    /// so `DocumentIds` maps those back to the IDs in the source.
+    ///
+    /// # Returns
+    /// * `Result<(Vec<Text>, Option<DocumentIds>)>` - Every document's text and language, and the external IDs
+    ///   if the source has them (`id_key` is set, or the format provides IDs)
+    ///
+    /// # Errors
+    /// Returns error if the corpus cannot be read, or a document is missing its ID or shares it with another
+    pub fn load_with_ids(&self) -> Result<(Vec<Text>, Option<DocumentIds>)> {
+        let (texts, ids): (Vec<Text>, Vec<Option<String>>) =
+            self.documents()?.collect::<Result<Vec<_>>>()?.into_iter().unzip();
+        Ok((texts, self.document_ids(ids)?))
+    }
+
+    /// Opens the corpus and returns an iterator that reads one document at a time, so the whole
+    /// corpus never has to be in memory. JSON arrays are the exception and are parsed up front.
+    ///
+    /// # Errors
+    /// Returns error if the key is invalid or the corpus cannot be opened. Errors reading individual
+    /// documents are returned by the iterator
+    pub fn documents(&self) -> Result<DocumentIter> {
+        self.documents_counting(&BytesRead::default())
+    }
+
+    /// Same as `documents`, but adds the bytes read from the corpus's files to `bytes_read` as the iterator
+    /// advances, for progress against `size_on_disk`
+    ///
+    /// # Errors
+    /// Returns error if the key is invalid or the corpus cannot be opened
+    pub fn documents_counting(&self, bytes_read: &BytesRead) -> Result<DocumentIter> {
+        let mut selector = KeySelector::parse(&self.key, &self.joiner)?;
+        selector.language = self.lang_key.as_deref().map(str::parse).transpose()?;
+        let id = match (&self.id_key, self.format) {
+            (Some(id_key), _) => Some(id_key.parse::<FieldSpec>()?),
+            (None, CorpusFormat::Beir) => Some("_id".parse::<FieldSpec>()?),
+            (None, _) => None,
+        };
+
+        let documents = match self.format {
+            CorpusFormat::Jsonl => read_jsonl(&self.path, selector, id, bytes_read),
+            CorpusFormat::Csv => read_delimited(&self.path, &selector, id.as_ref(), b',', bytes_read),
+            CorpusFormat::Tsv => read_delimited(&self.path, &selector, id.as_ref(), b'\t', bytes_read),
+            CorpusFormat::TextDir => read_text_dir(&self.path, bytes_read),
+            CorpusFormat::JsonArray => read_json_array(&self.path, selector, id, bytes_read),
+            CorpusFormat::Beir => read_beir(&self.path, id, bytes_read),
+        }?;
+
+        if !self.preprocessor.is_active() {
+            return Ok(documents);
+        }
+        let preprocessor = self.preprocessor.clone();
+        Ok(Box::new(documents.map(move |document| {
+            document.map(|(mut text, id)| {
+                text.text = preprocessor.apply(&text.text);
+                (text, id)
+            })
+        })))
+    }
+
+    /// The size of the corpus on disk in bytes, used to size progress bars when streaming
+    ///
+    /// # Errors
+    /// Returns error if the file or directory cannot be read
+    pub fn size_on_disk(&self) -> Result<u64> {
+        let metadata = fs::metadata(&self.path)?;
+        if !metadata.is_dir() {
+            return Ok(metadata.len());
+        }
+        // Only files are read as documents, see `read_text_dir`
+        fs::read_dir(&self.path)?
+            .map(|entry| {
+                let metadata = entry?.path().metadata()?;
+                Ok(if metadata.is_file() { metadata.len() } else { 0 })
+            })
+            .sum()
+    }
+
+    /// Turns the IDs read from the documents into a `DocumentIds`, if this source has external IDs
+    ///
+    /// # Arguments
+    /// * `ids` - The ID of each document, in corpus order, as returned by `documents`
+    ///
+    /// # Errors
+    /// Returns error if a document is missing its ID or shares it with another
+    pub fn document_ids(&self, ids: Vec<Option<String>>) -> Result<Option<DocumentIds>> {
+        if self.id_key.is_none() && !matches!(self.format, CorpusFormat::TextDir | CorpusFormat::Beir) {
+            return Ok(None);
+        }
+
+        let ids = ids
+            .into_iter()
+            .enumerate()
+            .map(|(index, id)| {
+                id.ok_or_else(|| BM25Error::Corpus(format!("document {index} in {} has no ID", self.path)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Some(DocumentIds::new(ids)?))
+    }
 }
 
 /// Reads JSON lines file and extracts text field values
 ///
 /// # Arguments
 /// * `filename` - Path to JSON lines file
-/// * `key` - The key in the JSON string that holds the main body of text
+/// * `key` - The key in the JSON string that holds the main body of text. Can be a nested path or several
+///   weighted keys, see `KeySelector`. Multiple keys are joined with a space
 ///
 /// # Returns
 /// * `Result<Vec<String>>` - Vector of text values
@@ -31,16 +457,235 @@ fn remove_numbers(input: &str) -> String {
 /// # Errors
 /// Returns error if file cannot be read or JSON is invalid
 pub fn return_data_as_string(filename: &str, key: &str) -> Result<Vec<String>> {
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
-    let result_vec: Vec<String> = reader
-        .lines()
-        .map(|line| -> Result<String> {
-            let line = line?;
-            let json_val: Value = serde_json::from_str(&line)?;
-            Ok(json_val[key].to_string())
+    read_jsonl(filename, KeySelector::parse(key, DEFAULT_JOINER)?, None, &BytesRead::default())?
+        .map(|document| Ok(document?.0.text))
+        .collect()
+}
+
+/// Reads JSON lines file and extracts the selected text (and ID, if asked for) from each line
+///
+/// # Errors
+/// Returns error if file cannot be opened. JSON errors are returned by the iterator
+fn read_jsonl(
+    filename: &str,
+    selector: KeySelector,
+    id: Option<FieldSpec>,
+    bytes_read: &BytesRead,
+) -> Result<DocumentIter> {
+    let reader = BufReader::new(bytes_read.open(filename)?);
+    Ok(Box::new(reader.lines().map(move |line| {
+        let line = line?;
+        let json_val: Value = serde_json::from_str(&line)?;
+        Ok((selector.select_json(&json_val), json_id(&json_val, id.as_ref())))
+    })))
+}
+
+/// Converts a JSON value into document text. Strings are taken as they are rather than quoted and escaped,
+/// and arrays are the text of their elements joined with a space
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        Value::Array(values) => values
+            .iter()
+            .map(value_text)
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "),
+        other => other.to_string(),
+    }
+}
+
+/// Reads a document's external ID from a parsed JSON document. Strings and numbers are accepted
+fn json_id(json_val: &Value, id: Option<&FieldSpec>) -> Option<String> {
+    match json_val.pointer(&id?.pointer)? {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// Reads a CSV or TSV file with a header row and extracts the selected columns
+///
+/// # Arguments
+/// * `filename` - Path to the file
+/// * `selector` - The columns that hold the text, by header name
+/// * `id` - The column that holds the document ID, if any
+/// * `delimiter` - Field delimiter, `,` or a tab
+///
+/// # Errors
+/// Returns error if the file cannot be opened or is missing a column. Malformed records are returned by the iterator
+fn read_delimited(
+    filename: &str,
+    selector: &KeySelector,
+    id: Option<&FieldSpec>,
+    delimiter: u8,
+    bytes_read: &BytesRead,
+) -> Result<DocumentIter> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_reader(bytes_read.open(filename)?);
+
+    let headers = reader.headers()?.clone();
+    let column = |field: &FieldSpec| {
+        headers
+            .iter()
+            .position(|header| header == field.name)
+            .ok_or_else(|| BM25Error::Corpus(format!("{filename} has no column named {}", field.name)))
+    };
+    let columns = selector.fields.iter().map(column).collect::<Result<Vec<_>>>()?;
+    let id_column = id.map(column).transpose()?;
+    let language_column = selector.language.as_ref().map(column).transpose()?;
+    let selector = selector.clone();
+
+    Ok(Box::new(reader.into_records().map(move |record| {
+        let record = record?;
+        let id = id_column
+            .and_then(|column| record.get(column))
+            .filter(|id| !id.is_empty())
+            .map(str::to_string);
+        Ok((selector.select_record(&columns, language_column, &record), id))
+    })))
+}
+
+/// Reads every file in a directory as one document, using the file name as its ID.
+/// Files are read in name order so document IDs are stable
+///
+/// # Errors
+/// Returns error if the directory cannot be read. Errors reading files are returned by the iterator
+fn read_text_dir(dirname: &str, bytes_read: &BytesRead) -> Result<DocumentIter> {
+    let mut paths = fs::read_dir(dirname)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    paths.retain(|path| path.is_file());
+    paths.sort();
+
+    let bytes_read = bytes_read.clone();
+    Ok(Box::new(paths.into_iter().map(move |path| {
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+        let mut text = String::new();
+        bytes_read.open(&path)?.read_to_string(&mut text)?;
+        Ok((text.into(), name))
+    })))
+}
+
+/// Reads a file holding a single JSON array of documents. The array is parsed in full before iterating
+///
+/// # Errors
+/// Returns error if the file cannot be read or isn't a JSON array
+fn read_json_array(
+    filename: &str,
+    selector: KeySelector,
+    id: Option<FieldSpec>,
+    bytes_read: &BytesRead,
+) -> Result<DocumentIter> {
+    let reader = BufReader::new(bytes_read.open(filename)?);
+    let json_val: Value = serde_json::from_reader(reader)?;
+    let Value::Array(documents) = json_val else {
+        return Err(BM25Error::Corpus(format!("{filename} is not a JSON array")));
+    };
+
+    Ok(Box::new(documents.into_iter().map(move |document| {
+        Ok((selector.select_json(&document), json_id(&document, id.as_ref())))
+    })))
+}
+
+/// Reads a MS MARCO / BEIR `corpus.jsonl`, joining each document's title and text
+///
+/// # Errors
+/// Returns error if file cannot be opened. JSON errors are returned by the iterator
+fn read_beir(filename: &str, id: Option<FieldSpec>, bytes_read: &BytesRead) -> Result<DocumentIter> {
+    let reader = BufReader::new(bytes_read.open(filename)?);
+    Ok(Box::new(reader.lines().map(move |line| {
+        let json_val: Value = serde_json::from_str(&line?)?;
+        let text = value_text(&json_val["text"]);
+        let text = match json_val["title"].as_str() {
+            Some(title) if !title.is_empty() => format!("{} {text}", value_text(&json_val["title"])),
+            _ => text,
+        };
+        Ok((text.into(), json_id(&json_val, id.as_ref())))
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_formats() {
+        assert_eq!(CorpusFormat::detect("data/nyt.jsonl").unwrap(), CorpusFormat::Jsonl);
+        assert_eq!(CorpusFormat::detect("scifact/corpus.jsonl").unwrap(), CorpusFormat::Beir);
+        assert_eq!(CorpusFormat::detect("docs.TSV").unwrap(), CorpusFormat::Tsv);
+        assert_eq!(CorpusFormat::detect("docs.json").unwrap(), CorpusFormat::JsonArray);
+        assert!(CorpusFormat::detect("docs.parquet").is_err());
+        assert_eq!("text-dir".parse::<CorpusFormat>().unwrap(), CorpusFormat::TextDir);
+    }
+
+    #[test]
+    fn read_csv_column() {
+        let path = std::env::temp_dir().join("bm25_calc_read_csv_column.csv");
+        fs::write(&path, "id,body,lang\n1,\"Apples, oranges\",en\n2,papayas,\n").unwrap();
+
+        let mut source = CorpusSource::new(path.to_str().unwrap(), "body", None).unwrap();
+        assert_eq!(source.load().unwrap(), vec!["Apples, oranges".into(), "papayas".into()]);
+
+        // The language is kept next to the text, and an empty one is none
+        source.lang_key = Some("lang".to_string());
+        let english = |text: &str| Text::new(text.to_string(), Some("en"));
+        assert_eq!(source.load().unwrap(), vec![english("Apples, oranges"), "papayas".into()]);
+
+        source.preprocessor = Preprocessor::new(&crate::preprocess::PreprocessConfig {
+            lowercase: true,
+            ..Default::default()
         })
-        .collect::<Result<_>>()?;
+        .unwrap();
+        assert_eq!(source.load().unwrap(), vec![english("apples, oranges"), "papayas".into()]);
+
+        let missing = CorpusSource::new(path.to_str().unwrap(), "text", None).unwrap();
+        assert!(missing.load().is_err());
+    }
+
+    #[test]
+    fn select_nested_weighted_fields() {
+        let selector = KeySelector::parse("title^2,article.body,/meta/tags/0", " | ").unwrap();
+        assert_eq!(selector.fields[1].pointer, "/article/body");
+
+        let json_val: Value = serde_json::json!({
+            "title": 1,
+            "article": {"body": 2},
+            "meta": {"tags": [3, 4]}
+        });
+        assert_eq!(selector.select_json(&json_val).text, "1 | 1 | 2 | 3");
+
+        // Missing fields are skipped rather than read as null
+        let json_val: Value = serde_json::json!({"article": {"body": 2}});
+        assert_eq!(selector.select_json(&json_val).text, "2");
+
+        // Strings are read raw, not as quoted JSON
+        let selector = KeySelector::parse("title,tags", " ").unwrap();
+        let json_val: Value = serde_json::json!({"title": "Say \"hi\"", "tags": ["a", null, "b"]});
+        assert_eq!(selector.select_json(&json_val).text, "Say \"hi\" a b");
+
+        assert!(KeySelector::parse("title^0", " ").is_err());
+        assert!(KeySelector::parse("title,", " ").is_err());
+    }
+
+    #[test]
+    fn read_external_ids() {
+        let path = std::env::temp_dir().join("bm25_calc_read_external_ids.jsonl");
+        fs::write(&path, "{\"meta\": {\"id\": \"nyt-7\"}, \"text\": \"a\"}\n{\"meta\": {\"id\": 12}, \"text\": \"b\"}\n").unwrap();
+
+        let mut source = CorpusSource::new(path.to_str().unwrap(), "text", None).unwrap();
+        assert!(source.load_with_ids().unwrap().1.is_none());
+
+        source.id_key = Some("meta.id".to_string());
+        let (texts, ids) = source.load_with_ids().unwrap();
+        let ids = ids.unwrap();
+        assert_eq!(texts, ["a".into(), "b".into()]);
+        assert_eq!(ids.external(0), Some("nyt-7"));
+        assert_eq!(ids.internal("12"), Some(1));
 
-    Ok(result_vec)
+        source.id_key = Some("missing".to_string());
+        assert!(source.load_with_ids().is_err());
+    }
 }
@@ -0,0 +1,173 @@
+use crate::error::Result;
+use crate::hashing::{sample_bins, BinHasher};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// How evenly a hash spreads an alphabet's choices over the bins.
+///
+/// Every keyword's `min(d, max_bins)` distinct choices are counted as hits on their bins. With a uniform hash each
+/// bin expects the same number of hits, which the chi-square statistic tests. The collision rate is measured
+/// on the raw draws, before `hashing::sample_bins` resamples choices that landed on a bin the keyword already had.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HashDiagnostics {
+    /// Number of keywords hashed
+    pub keywords: usize,
+    /// Number of hash choices per keyword
+    pub d: usize,
+    /// Number of bins
+    pub max_bins: usize,
+    /// Hits on each bin
+    pub observed: Vec<usize>,
+    /// Hits each bin expects under a uniform hash
+    pub expected: f64,
+    /// Pearson's chi-square statistic of `observed` against `expected`
+    pub chi_square: f64,
+    /// Degrees of freedom of the test, one less than the number of bins
+    pub degrees_of_freedom: usize,
+    /// Probability of a chi-square at least this large under a uniform hash (Wilson-Hilferty approximation)
+    pub p_value: f64,
+    /// Fraction of keywords whose raw draws hit the same bin twice
+    pub collision_rate: f64,
+    /// The collision rate a uniform hash gives, `1 - prod(1 - i / max_bins)` over the d draws
+    pub expected_collision_rate: f64,
+    /// Draws thrown away by unbiased range reduction
+    pub rejected: usize,
+    /// Draws that repeated one of the keyword's bins and were resampled
+    pub repeated: usize,
+}
+
+impl HashDiagnostics {
+    /// Hashes every keyword and measures the spread
+    ///
+    /// # Arguments
+    /// * `keywords` - The alphabet
+    /// * `d` - Number of hash choices
+    /// * `max_bins` - Number of bins
+    /// * `hasher` - The hash function to test
+    pub fn compute<'a>(
+        keywords: impl IntoIterator<Item = &'a str>,
+        d: usize,
+        max_bins: usize,
+        hasher: &dyn BinHasher,
+    ) -> Self {
+        let mut observed = vec![0; max_bins];
+        let mut num_keywords = 0;
+        let mut collided = 0;
+        let mut rejected = 0;
+        let mut repeated = 0;
+
+        for keyword in keywords {
+            let sample = sample_bins(keyword, d, max_bins, hasher);
+            for &bin in &sample.bins {
+                observed[bin] += 1;
+            }
+            num_keywords += 1;
+            rejected += sample.rejected;
+            repeated += sample.repeated;
+            if sample.repeated > 0 {
+                collided += 1;
+            }
+        }
+
+        let choices = d.min(max_bins);
+        let expected = (num_keywords * choices) as f64 / max_bins.max(1) as f64;
+        let chi_square = if expected > 0.0 {
+            observed
+                .iter()
+                .map(|&hits| (hits as f64 - expected).powi(2) / expected)
+                .sum()
+        } else {
+            0.0
+        };
+        let degrees_of_freedom = max_bins.saturating_sub(1);
+        let expected_collision_rate =
+            1.0 - (0..choices).map(|i| 1.0 - i as f64 / max_bins as f64).product::<f64>();
+
+        Self {
+            keywords: num_keywords,
+            d,
+            max_bins,
+            observed,
+            expected,
+            chi_square,
+            degrees_of_freedom,
+            p_value: chi_square_p_value(chi_square, degrees_of_freedom),
+            collision_rate: collided as f64 / num_keywords.max(1) as f64,
+            expected_collision_rate,
+            rejected,
+            repeated,
+        }
+    }
+
+    /// The fewest hits on any bin
+    pub fn min_observed(&self) -> usize {
+        self.observed.iter().copied().min().unwrap_or(0)
+    }
+
+    /// The most hits on any bin
+    pub fn max_observed(&self) -> usize {
+        self.observed.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Writes expected and observed hits for every bin as CSV
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be written
+    pub fn write_csv(&self, filename: &str) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writeln!(writer, "bin,expected,observed")?;
+        for (bin, hits) in self.observed.iter().enumerate() {
+            writeln!(writer, "{},{},{}", bin, self.expected, hits)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Upper tail probability of a chi-square statistic, using the Wilson-Hilferty normal approximation
+#[allow(clippy::suboptimal_flops)]
+fn chi_square_p_value(chi_square: f64, degrees_of_freedom: usize) -> f64 {
+    if degrees_of_freedom == 0 {
+        return 1.0;
+    }
+    let k = degrees_of_freedom as f64;
+    let variance = 2.0 / (9.0 * k);
+    let z = ((chi_square / k).cbrt() - (1.0 - variance)) / variance.sqrt();
+    0.5 * erfc(z / std::f64::consts::SQRT_2)
+}
+
+/// Complementary error function, Abramowitz and Stegun 7.1.26 (absolute error below 1.5e-7)
+#[allow(clippy::suboptimal_flops)]
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let polynomial = t
+        * (0.254_829_592
+            + t * (-0.284_496_736 + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let erfc = polynomial * (-x * x).exp();
+    if x >= 0.0 {
+        erfc
+    } else {
+        2.0 - erfc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::Sha256Hasher;
+
+    #[test]
+    fn uniform_hash_passes() {
+        let keywords: Vec<String> = (0..5000).map(|i| format!("word{i}")).collect();
+        let diagnostics = HashDiagnostics::compute(keywords.iter().map(String::as_str), 3, 100, &Sha256Hasher);
+
+        assert_eq!(diagnostics.observed.iter().sum::<usize>(), 15000);
+        assert!((diagnostics.expected - 150.0).abs() < 1e-9);
+        assert!(diagnostics.p_value > 0.001, "p = {}", diagnostics.p_value);
+        assert!((diagnostics.collision_rate - diagnostics.expected_collision_rate).abs() < 0.01);
+
+        // A chi-square at its mean is unremarkable, one far above it isn't
+        assert!((chi_square_p_value(99.0, 99) - 0.5).abs() < 0.05);
+        assert!(chi_square_p_value(200.0, 99) < 1e-6);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::error::{BM25Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+/// Maps the `u32` document IDs used by the search engine and bins back to the IDs in the source corpus.
+///
+/// The search engine numbers documents by their position in the corpus, so these IDs change as soon
+/// as the file is re-sorted. Save this next to the bins to keep them meaningful.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DocumentIds {
+    /// External ID of each document, indexed by internal ID
+    external: Vec<String>,
+    /// External ID to internal ID
+    #[serde(skip)]
+    internal: HashMap<String, u32>,
+}
+
+impl DocumentIds {
+    /// Creates the mapping from the external IDs in corpus order
+    ///
+    /// # Errors
+    /// Returns error if two documents share an ID or there are more than `u32::MAX` documents
+    pub fn new(external: Vec<String>) -> Result<Self> {
+        let mut internal = HashMap::with_capacity(external.len());
+        for (index, id) in external.iter().enumerate() {
+            if internal.insert(id.clone(), u32::try_from(index)?).is_some() {
+                return Err(BM25Error::Corpus(format!("document ID {id} is used more than once")));
+            }
+        }
+        Ok(Self { external, internal })
+    }
+
+    /// Returns the external ID of an internal document ID
+    pub fn external(&self, id: u32) -> Option<&str> {
+        self.external.get(id as usize).map(String::as_str)
+    }
+
+    /// Returns the internal document ID of an external ID
+    pub fn internal(&self, id: &str) -> Option<u32> {
+        self.internal.get(id).copied()
+    }
+
+    /// Converts a bin of internal IDs to sorted external IDs
+    pub fn resolve_bin(&self, bin: &HashSet<u32>) -> Vec<&str> {
+        let mut ids: Vec<&str> = bin.iter().filter_map(|&id| self.external(id)).collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// The number of documents
+    pub const fn len(&self) -> usize {
+        self.external.len()
+    }
+
+    /// Whether there are no documents
+    pub const fn is_empty(&self) -> bool {
+        self.external.is_empty()
+    }
+
+    /// Writes the mapping to `filename` as serde JSON
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be written
+    pub fn save(&self, filename: &str) -> Result<()> {
+        let writer = BufWriter::new(File::create(filename)?);
+        serde_json::to_writer(writer, self)?;
+        Ok(())
+    }
+
+    /// Reads a mapping previously written by `save`
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be read or is not a valid mapping
+    pub fn load(filename: &str) -> Result<Self> {
+        let reader = BufReader::new(File::open(filename)?);
+        let ids: Self = serde_json::from_reader(reader)?;
+        ids.reindex()
+    }
+
+    /// Rebuilds the external to internal lookup after deserialising, as only the external IDs are written
+    ///
+    /// # Errors
+    /// Returns error if two documents share an ID
+    pub(crate) fn reindex(self) -> Result<Self> {
+        Self::new(self.external)
+    }
+}
@@ -13,4 +13,26 @@ pub enum BM25Error {
     SerdeJSON(#[from] serde_json::Error),
     #[error("Unable to convert from an integer: {0}")]
     TryFromIntError(#[from] std::num::TryFromIntError),
+    #[error("Invalid experiment suite: {0}")]
+    Experiment(String),
+    #[error("Unable to read CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Unable to read corpus: {0}")]
+    Corpus(String),
+    #[error("Invalid range: {0}")]
+    InvalidRange(String),
+    #[error("Unusable posting snapshot: {0}")]
+    Snapshot(String),
+    #[error("Invalid placement: {0}")]
+    Placement(String),
+    #[error("Invalid preprocessing config: {0}")]
+    Preprocess(String),
+    #[error("Invalid tokenizer config: {0}")]
+    Tokenizer(String),
+    #[error("Invalid vocabulary filter: {0}")]
+    Vocabulary(String),
+    #[error("Invalid database: {0}")]
+    Database(String),
+    #[error("Memory limit of {limit} bytes reached while indexing (estimated {used} bytes in use)")]
+    MemoryLimit { used: usize, limit: usize },
 }
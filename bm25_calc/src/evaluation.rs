@@ -0,0 +1,197 @@
+use crate::bin_index::BinIndex;
+use crate::bm_calc::{candidate_bins, Metadata};
+use crate::error::Result;
+use crate::Config;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write as _};
+use tracing::info;
+
+/// How well the bins answer a single keyword query
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeywordRecall {
+    /// The keyword that was queried
+    pub keyword: String,
+    /// Fraction of the keyword's top-k documents found in its candidate bins
+    pub recall: f64,
+    /// Number of distinct documents in the union of the candidate bins
+    pub retrieved: usize,
+    /// Documents retrieved that were not in the keyword's top-k
+    pub false_positives: usize,
+}
+
+/// Result of simulating a client query for every keyword against a binned layout
+#[derive(Clone, Debug, Default)]
+pub struct Evaluation {
+    /// Per-keyword results, sorted by keyword
+    pub keywords: Vec<KeywordRecall>,
+    /// Mean of the per-keyword recall
+    pub mean_recall: f64,
+    /// Total number of documents retrieved that were not in the keyword's top-k
+    pub false_positives: usize,
+    /// Relevant documents retrieved over all documents retrieved, summed across keywords
+    pub precision: f64,
+}
+
+impl Evaluation {
+    /// The number of keywords that had at least one top-k document missing from their bins
+    pub fn incomplete_keywords(&self) -> usize {
+        self.keywords.iter().filter(|k| k.recall < 1.0).count()
+    }
+
+    /// Copies the aggregate results into the run's metadata so they show up in `print_table`
+    pub const fn apply_to(&self, metadata: &mut Metadata) {
+        metadata.recall = Some(self.mean_recall);
+        metadata.precision = Some(self.precision);
+        metadata.false_positives = Some(self.false_positives);
+    }
+
+    /// Writes the per-keyword results to a CSV
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be written
+    pub fn write_csv(&self, filename: &str) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writeln!(writer, "keyword,recall,retrieved,false_positives")?;
+        for k in &self.keywords {
+            writeln!(writer, "{},{},{},{}", k.keyword, k.recall, k.retrieved, k.false_positives)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Simulates a client querying every keyword against a binned layout.
+///
+/// For each keyword in `ground_truth` the client fetches the union of its d candidate bins and the whole stash,
+/// as it can't tell whether its keyword was stashed, and we check which of the keyword's top-k documents are in it.
+///
+/// # Arguments
+/// * `ground_truth` - Output of `bm_calc::top_k`, with the same k and filter_k as the bins run
+/// * `bins` - Bins returned by `bm_calc::top_k_bins_with_index`
+/// * `index` - The index returned with `bins`, for the keywords that went to the stash. Their documents are the
+///   ones in `ground_truth`
+/// * `config` - The config used to build `bins`, needed to recompute the candidate bins
+///
+/// # Errors
+/// Returns error if the hash needs a key and `config` has none
+pub fn evaluate(
+    ground_truth: &HashMap<String, HashSet<u32>>,
+    bins: &[HashSet<u32>],
+    index: &BinIndex,
+    config: &Config,
+) -> Result<Evaluation> {
+    let stash: HashSet<u32> = index
+        .stashed()
+        .filter_map(|keyword| ground_truth.get(keyword))
+        .flatten()
+        .copied()
+        .collect();
+
+    let mut words: Vec<&String> = ground_truth.keys().collect();
+    words.sort_unstable();
+
+    let mut keywords = Vec::with_capacity(words.len());
+    let mut relevant_retrieved = 0;
+    let mut total_retrieved = 0;
+
+    let hasher = config.hash.hasher(config)?;
+    for word in words {
+        let expected = &ground_truth[word];
+        let retrieved: HashSet<u32> = candidate_bins(word, config.d, config.max_bins, hasher.as_ref())
+            .into_iter()
+            .flat_map(|bin| bins[bin].iter().copied())
+            .chain(stash.iter().copied())
+            .collect();
+
+        let found = expected.iter().filter(|id| retrieved.contains(id)).count();
+        relevant_retrieved += found;
+        total_retrieved += retrieved.len();
+
+        keywords.push(KeywordRecall {
+            keyword: word.clone(),
+            recall: if expected.is_empty() { 1.0 } else { found as f64 / expected.len() as f64 },
+            retrieved: retrieved.len(),
+            false_positives: retrieved.len() - found,
+        });
+    }
+
+    let evaluation = Evaluation {
+        mean_recall: if keywords.is_empty() {
+            1.0
+        } else {
+            keywords.iter().map(|k| k.recall).sum::<f64>() / keywords.len() as f64
+        },
+        false_positives: total_retrieved - relevant_retrieved,
+        precision: if total_retrieved == 0 {
+            1.0
+        } else {
+            relevant_retrieved as f64 / total_retrieved as f64
+        },
+        keywords,
+    };
+
+    info!(
+        "Mean recall {:.4}, precision {:.4}, {} false positives. {} of {} keywords are missing results",
+        evaluation.mean_recall,
+        evaluation.precision,
+        evaluation.false_positives,
+        evaluation.incomplete_keywords(),
+        evaluation.keywords.len()
+    );
+
+    Ok(evaluation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataloader::Text;
+    use crate::bm_calc::{build_search_engine, get_alphabet, top_k, top_k_bins_with_index};
+    use crate::tokenizer::KeywordTokenizer;
+    use crate::snapshot::PostingSnapshot;
+
+    #[test]
+    fn bins_keep_full_recall() {
+        let corpus: Vec<Text> = [
+            "Apples, oranges, papayas, and more papayas.",
+            "The sky blushed pink as the sun dipped below the horizon.",
+            "She found a forgotten letter tucked inside an old book.",
+            "Papayas and apples fell from the sky.",
+        ]
+        .iter()
+        .map(|&s| Text::from(s))
+        .collect();
+        let tokenizer = KeywordTokenizer::default();
+        let alphabet = get_alphabet(&corpus, &tokenizer).unwrap();
+        let search = build_search_engine(&corpus, &tokenizer);
+        let config = Config {
+            d: 2,
+            max_bins: 4,
+            filter_k: 1,
+            min_overlap_factor: 1,
+            max_load_factor: 0,
+            save_result: false,
+            ..Config::default()
+        };
+
+        let snapshot = PostingSnapshot::build(&search, &alphabet, config.k);
+        let ground_truth = top_k(config.k, &snapshot, config.filter_k).unwrap();
+        let (_, bins, index) = top_k_bins_with_index(&snapshot, config.clone()).unwrap();
+        let evaluation = evaluate(&ground_truth, &bins, &index, &config).unwrap();
+
+        // One choice is always kept, so the client always finds every top-k document
+        assert_eq!(evaluation.keywords.len(), ground_truth.len());
+        assert!((evaluation.mean_recall - 1.0).abs() < f64::EPSILON);
+        assert!(evaluation.precision > 0.0 && evaluation.precision <= 1.0);
+
+        // Bins too small for any keyword send them all to the stash, which the client always fetches
+        let tiny = Config {
+            bin_capacity: Some(0),
+            ..config
+        };
+        let (metadata, bins, index) = top_k_bins_with_index(&snapshot, tiny.clone()).unwrap();
+        assert_eq!(metadata.stash_size, ground_truth.len());
+        assert!((evaluate(&ground_truth, &bins, &index, &tiny).unwrap().mean_recall - 1.0).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::bin_index::BinIndex;
+use crate::bm_calc::{self, Metadata};
+use crate::error::{BM25Error, Result};
+use crate::evaluation::{self, Evaluation};
+use crate::preprocess::PreprocessConfig;
+use crate::snapshot::PostingSnapshot;
+use crate::{plotter, Config};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use tracing::info;
+
+/// The suite that is run when no experiment file is passed on the command line
+pub const DEFAULT_SUITE: &str = include_str!("../experiments/default.json");
+
+/// Which calculation an experiment runs
+#[derive(Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExperimentKind {
+    /// Plain top-k, one bin per keyword (see `bm_calc::top_k`)
+    TopK,
+    /// d-choice hashing into bins (see `bm_calc::top_k_bins`)
+    #[default]
+    Bins,
+}
+
+/// A single named run in an experiment suite
+#[derive(Deserialize, Clone, Debug)]
+pub struct Experiment {
+    /// Name used for the table row and histogram title. `{max_bins}`, `{d}` and `{k}` are filled in before use
+    pub name: String,
+    /// Which calculation to run
+    #[serde(default)]
+    pub kind: ExperimentKind,
+    /// If set, `max_bins` is the number of top-k keywords divided by this value instead of the fixed config value
+    #[serde(default)]
+    pub bins_divisor: Option<usize>,
+    /// Parameters for the run. Any field left out of the file falls back to the base config
+    #[serde(flatten)]
+    pub config: Config,
+    /// Keys that are neither experiment nor config fields, such as a misspelt `max_bin`. `from_json` rejects
+    /// them, as `deny_unknown_fields` doesn't work alongside `flatten`
+    #[serde(flatten)]
+    unknown: HashMap<String, Value>,
+}
+
+/// A list of experiments, loaded from a JSON file of the form `{"experiments": [{"name": ..., "d": ...}, ...]}`
+#[derive(Deserialize, Clone, Debug)]
+pub struct ExperimentSuite {
+    /// The runs, in the order they should be executed and displayed
+    pub experiments: Vec<Experiment>,
+    /// How the corpus is cleaned before tokenizing, shared by every run. Replaces the command line's
+    /// preprocessing flags when given
+    #[serde(default)]
+    pub preprocess: Option<PreprocessConfig>,
+}
+
+impl Experiment {
+    /// Returns the experiment name with the placeholders replaced by the values used in the run
+    ///
+    /// # Arguments
+    /// * `max_bins` - The number of bins the run actually used
+    #[allow(clippy::literal_string_with_formatting_args)] // the placeholders are ours, not format!'s
+    pub fn display_name(&self, max_bins: usize) -> String {
+        self.name
+            .replace("{max_bins}", &max_bins.to_string())
+            .replace("{d}", &self.config.d.to_string())
+            .replace("{k}", &self.config.k.to_string())
+    }
+
+    /// Works out how many bins the run should use
+    ///
+    /// # Arguments
+    /// * `num_keywords` - The number of keywords that survived `filter_k` in plain top-k
+    pub const fn resolve_max_bins(&self, num_keywords: usize) -> usize {
+        match (self.kind, self.bins_divisor) {
+            (ExperimentKind::TopK, _) => num_keywords,
+            (ExperimentKind::Bins, Some(divisor)) => num_keywords / divisor,
+            (ExperimentKind::Bins, None) => self.config.max_bins,
+        }
+    }
+}
+
+impl ExperimentSuite {
+    /// Reads an experiment suite from a JSON file
+    ///
+    /// # Arguments
+    /// * `filename` - Path to the suite file
+    /// * `base` - Config whose values are used for any field an experiment leaves out
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be read or is not a valid suite
+    pub fn from_file(filename: &str, base: &Config) -> Result<Self> {
+        Self::from_json(&fs::read_to_string(filename)?, base)
+    }
+
+    /// Parses an experiment suite from a JSON string. See `from_file`
+    ///
+    /// # Errors
+    /// Returns error if the JSON is not a valid suite
+    pub fn from_json(json: &str, base: &Config) -> Result<Self> {
+        let mut suite: Value = serde_json::from_str(json)?;
+        let base = serde_json::to_value(base)?;
+
+        let experiments = suite
+            .get_mut("experiments")
+            .and_then(Value::as_array_mut)
+            .ok_or_else(|| BM25Error::Experiment("missing \"experiments\" array".to_string()))?;
+
+        // Lay every experiment over the base config so missing fields come from the command line
+        for experiment in experiments.iter_mut() {
+            let mut merged = base.clone();
+            if let (Some(merged), Some(fields)) = (merged.as_object_mut(), experiment.as_object()) {
+                merged.extend(fields.clone());
+            }
+            *experiment = merged;
+        }
+
+        let suite: Self = serde_json::from_value(suite)?;
+
+        if let Some(experiment) = suite.experiments.iter().find(|e| !e.unknown.is_empty()) {
+            let mut keys: Vec<&str> = experiment.unknown.keys().map(String::as_str).collect();
+            keys.sort_unstable();
+            return Err(BM25Error::Experiment(format!(
+                "\"{}\" has unknown fields: {}",
+                experiment.name,
+                keys.join(", ")
+            )));
+        }
+
+        if let Some(experiment) = suite.experiments.iter().find(|e| e.bins_divisor == Some(0)) {
+            return Err(BM25Error::Experiment(format!(
+                "\"{}\" has a bins_divisor of 0",
+                experiment.name
+            )));
+        }
+
+        Ok(suite)
+    }
+}
+
+/// Runs one experiment from the suite, plots its histogram and returns the display name and metadata
+///
+/// # Arguments
+/// * `experiment` - The experiment to run
+/// * `snapshot` - Search results for every keyword, built with at least the experiment's k
+/// * `top_k_cache` - Plain top-k results keyed on (k, filter_k), so they're only calculated once
+/// * `evaluate` - Whether to check recall and precision of the bins against plain top-k. Per-keyword results
+///   are written to `saved/` when `save_result` is set
+///
+/// # Errors
+/// Returns error if the experiment resolves to zero bins, the snapshot's k is too small or the calculation fails
+pub fn run_experiment(
+    experiment: &Experiment,
+    snapshot: &PostingSnapshot,
+    top_k_cache: &mut HashMap<(usize, usize), HashMap<String, HashSet<u32>>>,
+    evaluate: bool,
+) -> Result<(String, Metadata)> {
+    let mut config = experiment.config.clone();
+
+    let needs_top_k = evaluate
+        || experiment.kind == ExperimentKind::TopK
+        || experiment.bins_divisor.is_some();
+    let num_keywords = if needs_top_k {
+        match top_k_cache.entry((config.k, config.filter_k)) {
+            Entry::Occupied(entry) => entry.get().len(),
+            Entry::Vacant(entry) => entry
+                .insert(bm_calc::top_k(config.k, snapshot, config.filter_k)?)
+                .len(),
+        }
+    } else {
+        0
+    };
+
+    config.max_bins = experiment.resolve_max_bins(num_keywords);
+    let name = experiment.display_name(config.max_bins);
+
+    if config.max_bins == 0 {
+        return Err(BM25Error::Experiment(format!("\"{name}\" has no bins to fill")));
+    }
+
+    info!("Running experiment \"{}\"", name);
+
+    let (mut metadata, bins, index) = match experiment.kind {
+        ExperimentKind::TopK => {
+            let top_k_res = &top_k_cache[&(config.k, config.filter_k)];
+            (
+                Metadata::from_top_k(top_k_res, config.k),
+                top_k_res.values().cloned().collect(),
+                BinIndex::new(),
+            )
+        }
+        ExperimentKind::Bins => bm_calc::top_k_bins_with_index(snapshot, config.clone())?,
+    };
+
+    if evaluate {
+        match experiment.kind {
+            // Every keyword has its own bin, so the client gets exactly its top-k
+            ExperimentKind::TopK => Evaluation {
+                mean_recall: 1.0,
+                precision: 1.0,
+                ..Evaluation::default()
+            }
+            .apply_to(&mut metadata),
+            ExperimentKind::Bins => {
+                let evaluation =
+                    evaluation::evaluate(&top_k_cache[&(config.k, config.filter_k)], &bins, &index, &config)?;
+                evaluation.apply_to(&mut metadata);
+                if config.save_result {
+                    evaluation.write_csv(&format!("saved/{}_evaluation.csv", bm_calc::file_stem(&name)))?;
+                }
+            }
+        }
+    }
+
+    plotter::fullness_histogram(bins, true, &name, config.max_bins as i32)?;
+
+    Ok((name, metadata))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_suite_uses_base() {
+        let base = Config {
+            k: 7,
+            ..Config::default()
+        };
+        let suite = ExperimentSuite::from_json(DEFAULT_SUITE, &base).unwrap();
+
+        assert_eq!(suite.experiments[0].kind, ExperimentKind::TopK);
+        assert!(suite.experiments.iter().all(|e| e.config.k == 7));
+        assert_eq!(suite.experiments[1].config.d, 1);
+        assert_eq!(suite.experiments[1].resolve_max_bins(100), 10);
+        assert_eq!(suite.experiments[1].display_name(10), "1-choice 10-bins");
+
+        let misspelt = r#"{"experiments": [{"name": "typo", "max_bin": 8, "d": 2}]}"#;
+        let error = ExperimentSuite::from_json(misspelt, &base).unwrap_err().to_string();
+        assert!(error.contains("max_bin"), "{error}");
+        assert_eq!(bm_calc::file_stem("2-choice 10-bins, d=2 (k/3)"), "2-choice_10-bins_d=2_k_3");
+    }
+}
@@ -0,0 +1,285 @@
+use crate::error::{BM25Error, Result};
+use crate::Config;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use siphasher::sip::SipHasher24;
+use std::fmt;
+use std::hash::Hasher;
+use std::str::FromStr;
+
+/// Prefix of every hash input, so bin choices can't collide with other uses of the same hash and key
+const DOMAIN: &[u8] = b"bm25_calc/bin-choice/v1";
+/// Context string for deriving the keyed hashers' keys from `Config::hash_key`
+const KEY_CONTEXT: &str = "bm25_calc 2025 bin choice hash key";
+
+/// Maps a keyword and one of its hash choices to a 64-bit value, from which `bm_calc::candidate_bins` picks a bin.
+///
+/// Implementations hash `encode(keyword, choice)`, so every (keyword, choice) pair has a distinct input.
+pub trait BinHasher {
+    /// Hashes one of a keyword's choices
+    ///
+    /// # Arguments
+    /// * `keyword` - The keyword, as it appears in the alphabet
+    /// * `choice` - Which of the d hash functions, 0 to d - 1
+    fn hash(&self, keyword: &str, choice: usize) -> u64;
+}
+
+/// The bytes hashed for a keyword's choice.
+///
+/// That is the domain, the keyword's length as a little-endian u64, the keyword, then the choice as a little-endian
+/// u64. Length-prefixing the keyword and fixing the choice's width means no two (keyword, choice) pairs share an input
+pub fn encode(keyword: &str, choice: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(DOMAIN.len() + keyword.len() + 16);
+    bytes.extend_from_slice(DOMAIN);
+    bytes.extend_from_slice(&(keyword.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(keyword.as_bytes());
+    bytes.extend_from_slice(&(choice as u64).to_le_bytes());
+    bytes
+}
+
+/// Maps a 64-bit hash into `0..range` without modulo bias, using Lemire's multiply-shift.
+///
+/// Returns `None` for the few hashes that would make some outputs more likely than others. The caller draws
+/// another hash, so every bin is equally likely when `range` isn't a power of two.
+pub const fn reduce(hash: u64, range: u64) -> Option<u64> {
+    let product = hash as u128 * range as u128;
+    let low = product as u64;
+    if low < range {
+        // 2^64 mod range, the size of the biased region
+        let threshold = range.wrapping_neg() % range;
+        if low < threshold {
+            return None;
+        }
+    }
+    Some((product >> 64) as u64)
+}
+
+/// A keyword's bins with the work it took to draw them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChoiceSample {
+    /// The distinct bins, in the order they were drawn
+    pub bins: Vec<usize>,
+    /// Hashes thrown away by `reduce` to avoid bias
+    pub rejected: usize,
+    /// Hashes that landed on a bin the keyword already had
+    pub repeated: usize,
+}
+
+/// Draws `min(d, max_bins)` distinct bins for a keyword.
+///
+/// Hashes `(keyword, 0)`, `(keyword, 1)`, ... in turn, reduces each with `reduce`, and skips rejected hashes and
+/// bins already drawn, so the same keyword always gets the same bins in the same order.
+///
+/// # Arguments
+/// * `keyword` - The keyword
+/// * `d` - Number of bins wanted
+/// * `max_bins` - Number of bins
+/// * `hasher` - The hash function
+pub fn sample_bins(keyword: &str, d: usize, max_bins: usize, hasher: &dyn BinHasher) -> ChoiceSample {
+    let wanted = d.min(max_bins);
+    let mut sample = ChoiceSample {
+        bins: Vec::with_capacity(wanted),
+        ..ChoiceSample::default()
+    };
+    let mut attempt = 0;
+    while sample.bins.len() < wanted {
+        let hash = hasher.hash(keyword, attempt);
+        attempt += 1;
+        match reduce(hash, max_bins as u64) {
+            None => sample.rejected += 1,
+            Some(bin) => {
+                // Below max_bins, so it fits in a usize
+                let bin = bin as usize;
+                if sample.bins.contains(&bin) {
+                    sample.repeated += 1;
+                } else {
+                    sample.bins.push(bin);
+                }
+            }
+        }
+    }
+    sample
+}
+
+/// Which `BinHasher` places keywords
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HashKind {
+    /// SHA-256, unkeyed. Anyone can work out a keyword's bins
+    #[default]
+    Sha256,
+    /// BLAKE3 in keyed mode with a key derived from `Config::hash_key`
+    Blake3,
+    /// SipHash-2-4 with a secret 128-bit key derived from `Config::hash_key`
+    SipHash,
+    /// XXH3, seeded from `Config::hash_key`. Fast, but not a keyed PRF, so the seed only varies the layout
+    XxHash,
+}
+
+impl FromStr for HashKind {
+    type Err = BM25Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "sha256" | "sha-256" => Ok(Self::Sha256),
+            "blake3" => Ok(Self::Blake3),
+            "siphash" => Ok(Self::SipHash),
+            "xxhash" | "xxh3" => Ok(Self::XxHash),
+            _ => Err(BM25Error::Placement(format!("unknown hash {s}"))),
+        }
+    }
+}
+
+impl fmt::Display for HashKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+            Self::SipHash => "siphash",
+            Self::XxHash => "xxhash",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl HashKind {
+    /// Creates the hasher for a run
+    ///
+    /// # Arguments
+    /// * `config` - The run's config. The keyed hashers derive their key from `hash_key`
+    ///
+    /// # Errors
+    /// Returns error if the hash is keyed and `hash_key` is unset, as a fixed key would make every keyword's
+    /// bins predictable
+    pub fn hasher(self, config: &Config) -> Result<Box<dyn BinHasher>> {
+        let key = || {
+            config
+                .hash_key
+                .as_deref()
+                .map(|key| blake3::derive_key(KEY_CONTEXT, key.as_bytes()))
+                .ok_or_else(|| BM25Error::Placement(format!("{self} hashing is keyed but no hash key was given")))
+        };
+
+        Ok(match self {
+            Self::Sha256 => Box::new(Sha256Hasher),
+            Self::Blake3 => Box::new(Blake3Hasher { key: key()? }),
+            Self::SipHash => Box::new(SipHashHasher {
+                key: key()?[..16].try_into().unwrap_or_default(),
+            }),
+            Self::XxHash => Box::new(XxHasher {
+                seed: u64::from_le_bytes(key()?[..8].try_into().unwrap_or_default()),
+            }),
+        })
+    }
+}
+
+/// SHA-256 of the encoded input, the first 8 bytes read big-endian
+pub struct Sha256Hasher;
+
+impl BinHasher for Sha256Hasher {
+    fn hash(&self, keyword: &str, choice: usize) -> u64 {
+        let digest = Sha256::digest(encode(keyword, choice));
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest[0..8]);
+        u64::from_be_bytes(bytes)
+    }
+}
+
+/// Keyed BLAKE3 of the encoded input, the first 8 bytes read little-endian
+pub struct Blake3Hasher {
+    /// 256-bit key
+    key: [u8; 32],
+}
+
+impl BinHasher for Blake3Hasher {
+    fn hash(&self, keyword: &str, choice: usize) -> u64 {
+        let digest = blake3::keyed_hash(&self.key, &encode(keyword, choice));
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest.as_bytes()[0..8]);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+/// SipHash-2-4 of the encoded input
+pub struct SipHashHasher {
+    /// 128-bit key
+    key: [u8; 16],
+}
+
+impl BinHasher for SipHashHasher {
+    fn hash(&self, keyword: &str, choice: usize) -> u64 {
+        let mut hasher = SipHasher24::new_with_key(&self.key);
+        hasher.write(&encode(keyword, choice));
+        hasher.finish()
+    }
+}
+
+/// XXH3 64-bit of the encoded input
+pub struct XxHasher {
+    /// Seed for the hash
+    seed: u64,
+}
+
+impl BinHasher for XxHasher {
+    fn hash(&self, keyword: &str, choice: usize) -> u64 {
+        xxhash_rust::xxh3::xxh3_64_with_seed(&encode(keyword, choice), self.seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashers_are_keyed_and_unambiguous() {
+        // Hashing the keyword then the choice as text gave "a1" + "1" and "a" + "11" the same input
+        assert_ne!(encode("a1", 1), encode("a", 11));
+
+        let keyed = |hash: HashKind, key: &str| Config {
+            hash,
+            hash_key: Some(key.to_string()),
+            ..Config::default()
+        };
+        for kind in [HashKind::Sha256, HashKind::Blake3, HashKind::SipHash, HashKind::XxHash] {
+            let hasher = kind.hasher(&keyed(kind, "secret")).unwrap();
+            assert_eq!(hasher.hash("papaya", 2), kind.hasher(&keyed(kind, "secret")).unwrap().hash("papaya", 2));
+            assert_ne!(hasher.hash("papaya", 2), hasher.hash("papaya", 3));
+
+            // Only SHA-256 ignores the key, and it's the only one that works without one
+            let other = kind.hasher(&keyed(kind, "other")).unwrap().hash("papaya", 2);
+            assert_eq!(hasher.hash("papaya", 2) == other, kind == HashKind::Sha256, "{kind}");
+            let unkeyed = Config {
+                hash: kind,
+                ..Config::default()
+            };
+            assert_eq!(kind.hasher(&unkeyed).is_ok(), kind == HashKind::Sha256, "{kind}");
+        }
+
+        assert_eq!("xxh3".parse::<HashKind>().unwrap(), HashKind::XxHash);
+        assert!("md5".parse::<HashKind>().is_err());
+    }
+
+    #[test]
+    fn choices_are_distinct_and_unbiased() {
+        // With range 3, 2^64 mod 3 = 1 hash is biased and rejected
+        assert_eq!(reduce(0, 3), None);
+        assert_eq!(reduce(1, 3), Some(0));
+        assert_eq!(reduce(u64::MAX, 3), Some(2));
+        assert_eq!(reduce(u64::MAX, 1 << 10), Some((1 << 10) - 1));
+
+        let hasher = Sha256Hasher;
+        for word in ["apple", "papaya", "sky"] {
+            let sample = sample_bins(word, 4, 5, &hasher);
+            let mut bins = sample.bins.clone();
+            bins.sort_unstable();
+            bins.dedup();
+            assert_eq!(bins.len(), 4);
+            assert!(bins.iter().all(|&bin| bin < 5));
+            assert_eq!(sample_bins(word, 4, 5, &hasher), sample);
+        }
+        // Can't draw more distinct bins than there are
+        assert_eq!(sample_bins("apple", 8, 3, &hasher).bins.len(), 3);
+        assert!(sample_bins("apple", 2, 0, &hasher).bins.is_empty());
+        assert!("md5".parse::<HashKind>().is_err());
+    }
+}
@@ -0,0 +1,56 @@
+//! lib.rs - BM25 top-k calculation with d-choice hashing into bins.
+//!
+//! Load a corpus with `dataloader`, build the keyword alphabet and search engine with `bm_calc`,
+//! then run `bm_calc::top_k` or `bm_calc::top_k_bins` with a `Config`. Results can be displayed
+//! with `plotter`, and `experiment` and `sweep` run many configs over the same corpus.
+
+/// bin_index.rs - which bins each keyword was placed in, saved alongside the bins.
+pub mod bin_index;
+/// bm_calc.rs - crate responsible for calculating top-k and BM25 searching.
+pub mod bm_calc;
+/// config.rs - the parameters for a single top-k bins run.
+pub mod config;
+/// database.rs - bins padded to a fixed width for PIR servers.
+pub mod database;
+/// Crate that loads in data and puts it into a vector. Useful for the format the BM25 crate expects it.
+pub mod dataloader;
+/// diagnostics.rs - checks how uniformly a hash spreads keywords over the bins.
+pub mod diagnostics;
+/// document_ids.rs - maps internal document IDs back to the IDs in the source corpus.
+pub mod document_ids;
+/// error.rs - this holds a single enum that we can put our errors into.
+pub mod error;
+/// evaluation.rs - simulates client queries against bins and measures recall against plain top-k.
+pub mod evaluation;
+/// experiment.rs - named runs loaded from a suite file, so new experiments don't need a rebuild.
+pub mod experiment;
+/// hashing.rs - the hash functions that map a keyword's d choices to bins.
+pub mod hashing;
+/// placement.rs - strategies for choosing which of a keyword's d bins its results go into.
+pub mod placement;
+/// plotter.rs - histograms and tables for displaying results.
+pub mod plotter;
+/// preprocess.rs - text cleaning applied to every document before it is tokenized.
+pub mod preprocess;
+/// queries.rs - checks how the binned layout answers a workload of real multi-word queries.
+pub mod queries;
+/// ranking.rs - the pipeline that drops d-choice candidates by load, overlap or hash order.
+pub mod ranking;
+/// snapshot.rs - every keyword's search results, computed once and shared between runs.
+pub mod snapshot;
+/// streaming.rs - builds the alphabet and a BM25 index in one pass without keeping the corpus in memory.
+pub mod streaming;
+/// sweep.rs - runs top-k bins over a grid of configs and finds the best trade-offs.
+pub mod sweep;
+/// tokenizer.rs - the configurable tokenizer shared by the alphabet, the search engine and queries.
+pub mod tokenizer;
+/// vocabulary.rs - document frequencies of the keywords, and the filters that prune the alphabet.
+pub mod vocabulary;
+
+pub use bm25;
+pub use bin_index::BinIndex;
+pub use bm_calc::Metadata;
+pub use config::Config;
+pub use document_ids::DocumentIds;
+pub use error::{BM25Error, Result};
+pub use snapshot::PostingSnapshot;
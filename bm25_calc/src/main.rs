@@ -1,19 +1,31 @@
 //! main.rs - the main entrypoint into the calculator.
 
-/// bm_calc.rs - crate responsible for calculating top-k and BM25 searching.
-pub(crate) mod bm_calc;
-/// Crate that loads in data and puts it into a vector. Useful for the format the BM25 crate expects it.
-pub(crate) mod dataloader;
-/// error.rs - this holds a single enum that we can put our errors into.
-pub(crate) mod error;
-pub(crate) mod plotter;
-
-use std::collections::HashSet;
-
-use clap::{arg, command, Parser};
-use tracing::info;
-use crate::bm_calc::Metadata;
-use crate::plotter::print_table;
+use std::collections::{HashMap, HashSet};
+
+use bm25_calc::experiment::{self, ExperimentSuite, DEFAULT_SUITE};
+use bm25_calc::database::IdEncoding;
+use bm25_calc::diagnostics::HashDiagnostics;
+use bm25_calc::hashing::HashKind;
+use bm25_calc::placement::{KeywordOrder, PlacementKind};
+use bm25_calc::ranking::RankingPipeline;
+use bm25_calc::plotter::{print_hash_report, print_pruning_report, print_query_report, print_table};
+use bm25_calc::queries::{evaluate_queries, load_queries};
+use bm25_calc::sweep::{self, ParamRange, SweepGrid};
+use bm25_calc::dataloader::{CorpusFormat, CorpusSource};
+use bm25_calc::document_ids::DocumentIds;
+use bm25_calc::bm_calc::Searcher;
+use bm25_calc::streaming::{StreamingIndex, StreamingOptions};
+use bm25_calc::tokenizer::{ngram_counts, parse_language, KeywordTokenizer, NumericTokens, Stopwords, TokenizerConfig};
+use bm25_calc::bm25::Language;
+use bm25_calc::preprocess::{PreprocessConfig, Preprocessor, Substitution};
+use bm25_calc::vocabulary::{Frequency, VocabularyFilter};
+use bm25_calc::{bm_calc, BM25Error, Config, PostingSnapshot, Result};
+use clap::{Parser, Subcommand};
+use tracing::{info, warn};
+
+
+/// Bytes in a MiB, for the streaming size options
+const MIB: usize = 1024 * 1024;
 
 /// Clap structure used to quickly parse cmd args
 #[derive(Parser)]
@@ -31,40 +43,278 @@ struct Args {
     #[arg(long, default_value_t = 5)]
     filter_k: usize,
 
-    /// Path to the jsonl file to read
-    #[arg(short, long)]
-    file: String,
+    /// Path to the corpus to read. A file, or a directory of plain-text files. Not needed with --load-snapshot
+    #[arg(short, long, required_unless_present = "load_snapshot")]
+    file: Option<String>,
 
-    /// The key in the JSON which holds the file/text we want to search over.
+    /// The key in the JSON (or column in a CSV/TSV) which holds the file/text we want to search over.
+    /// Nested fields can be given as a dotted path (`article.body`) or JSON Pointer (`/article/body`).
+    /// Several keys can be comma separated, and `^n` repeats a key's text n times, e.g. `title^2,body`
     #[arg(long, long, default_value = "text")]
     key: String,
+
+    /// String placed between the text of multiple keys
+    #[arg(long, default_value = " ")]
+    joiner: String,
+
+    /// The key (or CSV column) holding each document's ID. When given, the mapping from internal to external
+    /// IDs is saved next to each run's bins and in any saved snapshot. BEIR corpora use `_id` and text directories the file name by default
+    #[arg(long)]
+    id_key: Option<String>,
+
+    /// Corpus format: jsonl, csv, tsv, text-dir, json-array or beir. Detected from the path if not given
+    #[arg(long)]
+    format: Option<CorpusFormat>,
+
+    /// Remove HTML/XML tags, comments, scripts and styles, and decode entities such as &amp;
+    #[arg(long)]
+    strip_html: bool,
+
+    /// Apply Unicode NFKC normalization, so ligatures and full-width characters match their plain forms
+    #[arg(long)]
+    nfkc: bool,
+
+    /// Remove web addresses
+    #[arg(long)]
+    remove_urls: bool,
+
+    /// Remove email addresses
+    #[arg(long)]
+    remove_emails: bool,
+
+    /// Remove numbers, including thousands separators and decimals, before tokenizing
+    #[arg(long)]
+    remove_numbers: bool,
+
+    /// Lowercase the text before the substitutions run
+    #[arg(long)]
+    lowercase: bool,
+
+    /// Replace matches of a regex, written as PATTERN=>REPLACEMENT (e.g. "colou?r=>color"). Runs after the other
+    /// cleaning flags. Can be given more than once
+    #[arg(long)]
+    substitute: Vec<Substitution>,
+
+    /// Language for stemming and built-in stopwords, by name or ISO 639-1 code (e.g. german or de)
+    #[arg(long, default_value = "english", value_parser = parse_language)]
+    language: Language,
+
+    /// Detect each document's language and tokenize it with that language's stemmer and stopwords. Keywords are
+    /// tagged with the language, e.g. de:haus, and --language is used when detection fails
+    #[arg(long)]
+    multilingual: bool,
+
+    /// The key (or CSV column) holding each document's language as a name or ISO 639-1 code, used instead of
+    /// detecting it. Implies --multilingual
+    #[arg(long)]
+    lang_key: Option<String>,
+
+    /// Keep words as they are instead of stemming them
+    #[arg(long)]
+    no_stemming: bool,
+
+    /// Stopwords to remove: built-in (the language's list), none, or the path of a file with one word per line
+    #[arg(long, default_value = "built-in")]
+    stopwords: Stopwords,
+
+    /// Keep accents and other non-ASCII characters instead of folding them to ASCII
+    #[arg(long)]
+    no_normalization: bool,
+
+    /// Shortest keyword kept, in characters
+    #[arg(long, default_value_t = 1)]
+    min_token_len: usize,
+
+    /// Longest keyword kept, in characters
+    #[arg(long)]
+    max_token_len: Option<usize>,
+
+    /// What to do with numbers: keep, drop, or placeholder (every number becomes the keyword "#")
+    #[arg(long, default_value = "keep")]
+    numbers: NumericTokens,
+
+    /// Also make keywords of phrases up to this many adjacent words, e.g. 2 adds bigrams such as new+york
+    #[arg(long, default_value_t = 1)]
+    ngrams: usize,
+
+    /// Drop keywords in fewer documents than this, a count (2 drops words seen once) or a fraction like 0.001 or 0.1%
+    #[arg(long)]
+    min_df: Option<Frequency>,
+
+    /// Drop keywords in more documents than this, a count or a fraction like 0.5 or 50%
+    #[arg(long)]
+    max_df: Option<Frequency>,
+
+    /// Drop phrase keywords in fewer documents than this, a count or a fraction. Single words are kept
+    #[arg(long)]
+    min_phrase_df: Option<Frequency>,
+
+    /// Drop phrase keywords whose words appear together less than this pointwise mutual information, e.g. 0 keeps
+    /// phrases whose words co-occur more often than chance
+    #[arg(long, allow_negative_numbers = true)]
+    min_pmi: Option<f64>,
+
+    /// Drop the N keywords that appear in the most documents
+    #[arg(long)]
+    drop_top_n: Option<usize>,
+
+    /// Drop keywords matching this regex. Can be given more than once
+    #[arg(long)]
+    deny: Vec<String>,
+
+    /// Keep at most this many keywords, those that appear in the most documents
+    #[arg(long)]
+    max_keywords: Option<usize>,
+
+    /// How each keyword's bins are chosen from its d choices: d-choice, least-loaded, max-overlap, cuckoo or random
+    #[arg(long, default_value = "d-choice")]
+    placement: PlacementKind,
+
+    /// The order keywords are placed in: alphabet, posting-size (most results first) or random (uses --seed)
+    #[arg(long, default_value = "alphabet")]
+    order: KeywordOrder,
+
+    /// Which of the d choices d-choice placement drops before inserting, as criterion:direction:count stages
+    /// (e.g. "overlap:lowest:1,load:highest:1"). Criteria are load, overlap, overlap-ratio and hash-order
+    #[arg(long)]
+    removal: Option<RankingPipeline>,
+
+    /// Most documents a bin may hold. Keywords that don't fit into any of their choices go to the stash, and saved
+    /// bins are padded to this width. Unbounded by default (cuckoo placement uses the average load)
+    #[arg(long)]
+    bin_capacity: Option<usize>,
+
+    /// How document IDs are stored in the saved binary database: u32 (little-endian) or bit-packed
+    #[arg(long, default_value = "u32")]
+    id_encoding: IdEncoding,
+
+    /// Longest chain of evictions cuckoo placement follows before putting a keyword in the stash
+    #[arg(long, default_value_t = 64)]
+    max_evictions: usize,
+
+    /// Hash that maps a keyword's choices to bins: sha256, blake3 (keyed), siphash (keyed) or xxhash (fast)
+    #[arg(long, default_value = "sha256")]
+    hash: HashKind,
+
+    /// Secret the keyed hashes derive their key from. Clients need the same key to find a keyword's bins
+    #[arg(long)]
+    hash_key: Option<String>,
+
+    /// Seed for placement strategies that make random choices
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Path to a JSON experiment suite. Values from the command line are used for any field an experiment leaves out. Runs the built-in suite if not given.
+    #[arg(short, long)]
+    experiments: Option<String>,
+
+    /// Check every keyword's candidate bins against plain top-k and report recall, precision and false positives
+    #[arg(long)]
+    evaluate: bool,
+
+    /// Read the corpus in chunks and build the alphabet and index in one pass, without keeping the document text in memory
+    #[arg(long)]
+    streaming: bool,
+
+    /// With --streaming, how much raw text to read at a time, in MiB
+    #[arg(long, default_value_t = 64)]
+    chunk_size_mb: usize,
+
+    /// With --streaming, stop if the estimated size of the alphabet and index goes over this many MiB
+    #[arg(long)]
+    memory_limit_mb: Option<usize>,
+
+    /// Number of threads used to search the alphabet. 0 uses one per core
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// Save every keyword's search results to this file, so later runs can skip BM25 with --load-snapshot
+    #[arg(long)]
+    save_snapshot: Option<String>,
+
+    /// Read search results saved with --save-snapshot instead of loading and searching the corpus.
+    /// The snapshot must have been saved with a k at least as large as any run's
+    #[arg(long, conflicts_with = "save_snapshot")]
+    load_snapshot: Option<String>,
+
+    /// Mode to run instead of the experiment suite
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Modes other than running an experiment suite
+#[derive(Subcommand)]
+enum Command {
+    /// Runs top-k bins for every combination of the given values and writes the results to CSV.
+    /// Values are comma separated lists of numbers or inclusive ranges, e.g. `1..4` or `256..1024:256`.
+    /// Fields that aren't given use the top-level arguments.
+    Sweep(SweepArgs),
+    /// Hashes every keyword's d choices and checks how uniformly they spread over the bins: a chi-square test,
+    /// the collision rate among a keyword's choices and the expected against observed hits per bin.
+    HashReport(HashReportArgs),
+    /// Runs a workload of real queries through BM25 and checks how much of each query's top-k the union of its
+    /// keywords' candidate bins holds. Needs --file, as the full engine answers the multi-word queries.
+    Queries(QueryArgs),
 }
 
+/// Arguments for the queries subcommand
+#[derive(clap::Args)]
+struct QueryArgs {
+    /// File of queries, one per line as plain text or JSON with a `text` or `query` field, and the language under
+    /// --lang-key if it is given
+    queries: String,
 
-#[derive(Copy, Clone, Debug)]
-pub struct Config {
-    pub k: usize,
-    pub d: usize,
-    pub max_bins: usize,
-    pub filter_k: usize,
-    pub max_load_factor: usize,
-    pub min_overlap_factor: usize,
-    pub save_result: bool,
+    /// Number of bins to place keywords into
+    #[arg(long, default_value_t = 1024)]
+    max_bins: usize,
+
+    /// CSV file for the recall of every query
+    #[arg(short, long, default_value = "saved/query_evaluation.csv")]
+    output: String,
 }
 
+/// Arguments for the hash-report subcommand
+#[derive(clap::Args)]
+struct HashReportArgs {
+    /// Number of bins to hash into
+    #[arg(long, default_value_t = 1024)]
+    max_bins: usize,
 
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            k: 10,
-            d: 4,
-            max_bins: 1024,
-            filter_k: 1,
-            max_load_factor: 1,
-            min_overlap_factor: 1,
-            save_result: true,
-        }
-    }
+    /// CSV file for the expected and observed hits on every bin
+    #[arg(short, long, default_value = "saved/hash_report.csv")]
+    output: String,
+}
+
+/// Arguments for the sweep subcommand
+#[derive(clap::Args)]
+struct SweepArgs {
+    /// Values of k to sweep over
+    #[arg(short, long)]
+    k: Option<ParamRange>,
+
+    /// Values of d to sweep over
+    #[arg(short, long)]
+    d: Option<ParamRange>,
+
+    /// Values of max_bins to sweep over
+    #[arg(long, default_value = "1024")]
+    max_bins: ParamRange,
+
+    /// Values of filter_k to sweep over
+    #[arg(long)]
+    filter_k: Option<ParamRange>,
+
+    /// Values of max_load_factor to sweep over
+    #[arg(long, default_value = "0")]
+    max_load_factor: ParamRange,
+
+    /// Values of min_overlap_factor to sweep over
+    #[arg(long, default_value = "0")]
+    min_overlap_factor: ParamRange,
+
+    /// CSV file to write one row per combination to
+    #[arg(short, long, default_value = "sweep.csv")]
+    output: String,
 }
 
 
@@ -76,246 +326,310 @@ fn main() {
 
     let args = Args::parse();
 
-    let k = args.k;
-    let filter_k = args.filter_k;
-
-    info!("Starting BM25 calculation");
-    let corpus = dataloader::return_data_as_string(&args.file, &args.key).unwrap();
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build_global()
+        .expect("Unable to start the thread pool");
+
+    let base = Config {
+        k: args.k,
+        d: args.d,
+        filter_k: args.filter_k,
+        placement: args.placement,
+        order: args.order,
+        removal: args.removal.clone(),
+        bin_capacity: args.bin_capacity,
+        id_encoding: args.id_encoding,
+        max_evictions: args.max_evictions,
+        seed: args.seed,
+        hash: args.hash,
+        hash_key: args.hash_key.clone(),
+        ..Config::default()
+    };
 
-    let alphabet = bm_calc::get_alphabet(&corpus).unwrap();
+    // Load the suite before the corpus so a bad file fails fast
+    let suite = match &args.command {
+        Some(_) => None,
+        None => Some(
+            args.experiments
+                .as_ref()
+                .map_or_else(
+                    || ExperimentSuite::from_json(DEFAULT_SUITE, &base),
+                    |path| ExperimentSuite::from_file(path, &base),
+                )
+                .expect("Unable to load experiment suite"),
+        ),
+    };
 
-    info!(
-        "The total number of files is {} and the alphabet size is {}",
-        corpus.len(),
-        alphabet.len()
+    // Keyed hashes need a key, so check every run's hash before spending time on the corpus
+    let configs: Vec<&Config> = suite.as_ref().map_or_else(
+        || vec![&base],
+        |suite| suite.experiments.iter().map(|experiment| &experiment.config).collect(),
     );
+    for config in configs {
+        config.hash.hasher(config).expect("Keyed hashes need --hash-key, or a hash_key in the suite");
+    }
 
-    let search = bm_calc::build_search_engine(corpus);
-
-
-    let top_k_res = bm_calc::top_k(k, &search, &alphabet, filter_k);
-    info!("Top K Done");
-    plotter::fullness_histogram(
-        top_k_res.values().cloned().collect(),
-        true,
-        &"Top K (No bins)".to_string(),
-        top_k_res.values().len() as i32,
-    )
-    .expect("TODO: panic message");
-
-    let max_bins = top_k_res.values().len() / 10;
-
-    let mut config = Config::default();
-
-    config.d = 1;
-    config.k = 10;
-    config.max_bins = max_bins;
-    config.filter_k = filter_k;
-    config.max_load_factor = 0;
-    config.min_overlap_factor = 0;
-    config.save_result = true;
-
-    let no_choice_bins =
-        bm_calc::top_k_bins(&search, &alphabet, config)
-            .expect("TODO: panic message");
-    plotter::fullness_histogram(
-        no_choice_bins.1.clone(),
-        true,
-        &format!("Top K 1-choice {max_bins}-bins"),
-        max_bins as i32,
-    )
-    .expect("TODO: panic message");
-
-    config.d = 2;
-    config.min_overlap_factor = 1;
-
-    let two_choice_bins =
-        bm_calc::top_k_bins(&search, &alphabet, config)
-            .expect("TODO: panic message");
-    plotter::fullness_histogram(
-        two_choice_bins.1.clone(),
-        true,
-        &format!("Top K 2-choice {max_bins}-bins").to_string(),
-        max_bins as i32,
-    )
-    .expect("TODO: panic message");
-
-    config.d = 3;
-    config.min_overlap_factor = 2;
-
-    let three_choice_bins =
-        bm_calc::top_k_bins(&search, &alphabet, config)
-            .expect("TODO: panic message");
-    plotter::fullness_histogram(
-        three_choice_bins.1.clone(),
-        true,
-        &format!("Top K 3-choice {max_bins}-bins").to_string(),
-        max_bins as i32,
-    )
-    .expect("TODO: panic message");
-
-    config.d = 3;
-    config.min_overlap_factor = 1;
-    config.max_load_factor = 1;
-
-    let three_choice_bins_remove_one =
-        bm_calc::top_k_bins(&search, &alphabet,config)
-            .expect("TODO: panic message");
-    plotter::fullness_histogram(
-        three_choice_bins_remove_one.1.clone(),
-        true,
-        &format!("3-choice, {max_bins}-bins and 1 max-load bin removed"),
-        max_bins as i32,
-    )
-    .expect("TODO: panic message");
-
-    config.d = 2;
-    config.min_overlap_factor = 0;
-    config.max_load_factor = 1;
-
-    let two_choice_bins_max_load =
-        bm_calc::top_k_bins(&search, &alphabet, config)
-            .expect("TODO: panic message");
-    plotter::fullness_histogram(
-        two_choice_bins_max_load.1.clone(),
-        true,
-        &format!("Top K 2-choice {max_bins}-bins, minimising load"),
-        max_bins as i32,
-    )
-    .expect("TODO: panic message");
-
-    // config.d = 100;
-    // config.max_load_factor = 10;
-    // config.min_overlap_factor = 89;
-    // let hundred_choice_ten_max_load =
-    //     bm_calc::top_k_bins(&search, &alphabet, config)
-    //         .expect("TODO: panic message");
-    // plotter::fullness_histogram(
-    //     hundred_choice_ten_max_load.1.clone(),
-    //     true,
-    //     &format!("Top K 100-choice {max_bins}-bins, remove 10 max load bins"),
-    //     max_bins as i32,
-    // )
-    // .expect("TODO: panic message");
-
-    config.d = 4;
-    config.min_overlap_factor = 1;
-    config.max_load_factor = 1;
-
-    // do 4-choice remove max load and min overlap. Store in other 2.
-    let four_choice_min_overlap_max_overlap =
-        bm_calc::top_k_bins(&search, &alphabet, config)
-            .expect("TODO: panic message");
-    plotter::fullness_histogram(
-        four_choice_min_overlap_max_overlap.1.clone(),
-        true,
-        &format!("4-choice {max_bins}-bins, remove 1 min overlap, 1 max load"),
-        max_bins as i32,
-    )
-        .expect("TODO: panic message");
-
-    let mut format_strings = Vec::new();
-    let mut results = Vec::new();
-
-    // Collect all format strings
-    format_strings.extend_from_slice(&[
-        format!("Naive 1-1 mapping with {}-bins", top_k_res.values().len()),
-        format!("1-choice {max_bins}-bins"),
-        format!("2-choice {max_bins}-bins"),
-        format!("3-choice {max_bins}-bins"),
-        format!("3-choice, {max_bins}-bins and 1 max-load bin removed"),
-        format!("2-choice {max_bins}-bins, minimising load"),
-        //format!("100-choice {max_bins}-bins, remove 10 max load bins"),
-        format!("4-choice {max_bins}-bins, remove 1 min overlap, 1 max load"),
-    ]);
-
-    let top_k_meta =  Metadata {
-        num_bins: top_k_res.keys().len(),
-        k,
-        d: 1,
-        removed_items: 0,
-        total_items: top_k_res.values().map(|set| set.len()).sum(),
-        average_load_per_bin: (top_k_res.values().map(|set| set.len()).sum::<usize>() / top_k_res.values().len()),
-        keywords_with_overlap: 0,
+    // One snapshot with the largest k serves every run
+    let max_k = match &args.command {
+        Some(Command::Sweep(sweep_args)) => sweep_args
+            .k
+            .as_ref()
+            .and_then(|k| k.0.last().copied())
+            .unwrap_or(base.k),
+        Some(Command::HashReport(_) | Command::Queries(_)) => base.k,
+        None => suite
+            .iter()
+            .flat_map(|suite| &suite.experiments)
+            .map(|experiment| experiment.config.k)
+            .max()
+            .unwrap_or(base.k),
     };
 
+    let preprocess = suite
+        .as_ref()
+        .and_then(|suite| suite.preprocess.clone())
+        .unwrap_or_else(|| PreprocessConfig {
+            strip_html: args.strip_html,
+            nfkc: args.nfkc,
+            remove_urls: args.remove_urls,
+            remove_emails: args.remove_emails,
+            remove_numbers: args.remove_numbers,
+            lowercase: args.lowercase,
+            substitutions: args.substitute.clone(),
+        });
+    let preprocessor = Preprocessor::new(&preprocess).expect("Unable to build the preprocessor");
+    if args.load_snapshot.is_some() && preprocess.is_active() {
+        warn!("Preprocessing is ignored with --load-snapshot, which was built from the cleaned corpus");
+    }
 
-    // Collect all results
-    results.extend_from_slice(&[
-        top_k_meta,
-        no_choice_bins.0,
-        two_choice_bins.0,
-        three_choice_bins.0,
-        three_choice_bins_remove_one.0,
-        two_choice_bins_max_load.0,
-        //hundred_choice_ten_max_load.0,
-        four_choice_min_overlap_max_overlap.0,
-    ]);
+    let tokenizer = KeywordTokenizer::new(&TokenizerConfig {
+        language: args.language.clone(),
+        multilingual: args.multilingual || args.lang_key.is_some(),
+        stemming: !args.no_stemming,
+        stopwords: args.stopwords.clone(),
+        normalization: !args.no_normalization,
+        min_token_len: args.min_token_len,
+        max_token_len: args.max_token_len,
+        numbers: args.numbers,
+        ngrams: args.ngrams,
+    })
+    .expect("Unable to build the tokenizer");
+
+    let filter = VocabularyFilter {
+        deny: args.deny.clone(),
+        min_df: args.min_df,
+        max_df: args.max_df,
+        min_phrase_df: args.min_phrase_df,
+        min_pmi: args.min_pmi,
+        drop_top_n: args.drop_top_n,
+        max_keywords: args.max_keywords,
+    };
+    if args.load_snapshot.is_some() && filter.is_active() {
+        warn!("The vocabulary filters are ignored with --load-snapshot, which already holds its alphabet");
+    }
 
-    print_table(&format_strings, &results).unwrap();
-}
+    // Only the queries subcommand needs the engine once every keyword has been searched
+    let mut search = match (&args.command, &args.load_snapshot) {
+        (Some(Command::Queries(_)), _) | (_, None) => Some(build_engine(&args, &preprocessor, &tokenizer, &filter).unwrap()),
+        _ => None,
+    };
 
-pub fn calculate_emd(bins1: &[HashSet<u32>], bins2: &[HashSet<u32>]) -> f64 {
-    // Get distributions (number of items in each bin)
-    let mut dist1: Vec<usize> = bins1.iter().map(|bin| bin.len()).collect();
-    let mut dist2: Vec<usize> = bins2.iter().map(|bin| bin.len()).collect();
+    let snapshot = match (&args.load_snapshot, &mut search) {
+        (Some(path), _) => {
+            let snapshot = PostingSnapshot::load(path).expect("Unable to load posting snapshot");
+            info!("Loaded top {} results for {} keywords from {}", snapshot.k(), snapshot.len(), path);
+            snapshot
+        }
+        (None, Some(engine)) => {
+            let snapshot = PostingSnapshot::build(engine.search.as_ref(), &engine.alphabet, max_k)
+                .with_document_ids(engine.document_ids.take());
+            if let Some(path) = &args.save_snapshot {
+                snapshot.save(path).expect("Unable to save posting snapshot");
+                info!("Saved top {} results for {} keywords to {}", snapshot.k(), snapshot.len(), path);
+            }
+            snapshot
+        }
+        (None, None) => unreachable!("the engine is built whenever there is no snapshot to load"),
+    };
 
-    // Sort the distributions to minimize total distance
-    dist1.sort_unstable();
-    dist2.sort_unstable();
+    match args.command {
+        Some(Command::Sweep(sweep_args)) => {
+            run_sweep(sweep_args, &snapshot, base).expect("Sweep failed");
+        }
+        Some(Command::HashReport(report_args)) => {
+            let hasher = base.hash.hasher(&base).expect("Unable to create the hasher");
+            let alphabet = snapshot.alphabet();
+            let diagnostics = HashDiagnostics::compute(
+                alphabet.iter().map(String::as_str),
+                base.d,
+                report_args.max_bins,
+                hasher.as_ref(),
+            );
+            print_hash_report(&diagnostics);
+            diagnostics.write_csv(&report_args.output).expect("Unable to write the hash report");
+            info!("Wrote the hits on every bin to {}", report_args.output);
+        }
+        Some(Command::Queries(query_args)) => {
+            let Some(Engine { search, .. }) = &search else {
+                unreachable!("the engine is always built for the queries subcommand")
+            };
+            let config = Config {
+                max_bins: query_args.max_bins,
+                save_result: false,
+                ..base
+            };
+            let queries = load_queries(&query_args.queries, args.lang_key.as_deref()).expect("Unable to load queries");
+            let (_, bins, index) =
+                bm_calc::top_k_bins_with_index(&snapshot, config.clone()).expect("Unable to build bins");
+            let evaluation = evaluate_queries(search.as_ref(), &queries, &tokenizer, &snapshot, &bins, &index, &config)
+                .expect("Query evaluation failed");
+            print_query_report(&evaluation);
+            evaluation.write_csv(&query_args.output).expect("Unable to write the query evaluation");
+            info!("Wrote the recall of {} queries to {}", evaluation.queries.len(), query_args.output);
+        }
+        None => {
+            let mut top_k_cache = HashMap::new();
+            let mut names = Vec::new();
+            let mut results = Vec::new();
+
+            for experiment in suite.iter().flat_map(|suite| &suite.experiments) {
+                let (name, metadata) =
+                    experiment::run_experiment(experiment, &snapshot, &mut top_k_cache, args.evaluate)
+                        .expect("Experiment failed");
+                names.push(name);
+                results.push(metadata);
+            }
+
+            print_table(&names, &results).unwrap();
+        }
+    }
+}
 
-    // Make sure distributions have same length
-    let num_bins = dist1.len().max(dist2.len());
-    dist1.resize(num_bins, 0);
-    dist2.resize(num_bins, 0);
+/// The search engine over a corpus with its pruned alphabet
+struct Engine {
+    /// Searches the corpus
+    search: Box<dyn Searcher>,
+    /// The keywords to search for
+    alphabet: HashSet<String>,
+    /// The corpus's external document IDs, if it has them
+    document_ids: Option<DocumentIds>,
+}
 
-    // Calculate total items (should be same in both distributions)
-    let total_items1: usize = dist1.iter().sum();
-    let total_items2: usize = dist2.iter().sum();
+/// Loads the corpus, builds the search engine and the alphabet, and prunes the alphabet
+///
+/// # Arguments
+/// * `args` - The command line, for the corpus and streaming options
+/// * `preprocessor` - Cleans each document before it is tokenized
+/// * `tokenizer` - Splits the corpus for both the alphabet and the engine
+/// * `filter` - Which keywords to drop from the alphabet
+///
+/// # Errors
+/// Returns error if the corpus can't be read or a filter is invalid
+fn build_engine(
+    args: &Args,
+    preprocessor: &Preprocessor,
+    tokenizer: &KeywordTokenizer,
+    filter: &VocabularyFilter,
+) -> Result<Engine> {
+    let Some(file) = &args.file else {
+        return Err(BM25Error::Corpus("no corpus file given".to_string()));
+    };
 
-    if total_items1 != total_items2 {
-        println!(
-            "Warning: Distributions have different total items: {} vs {}",
-            total_items1, total_items2
+    info!("Starting BM25 calculation");
+    let mut source = CorpusSource::new(file, &args.key, args.format)?;
+    source.joiner.clone_from(&args.joiner);
+    source.id_key.clone_from(&args.id_key);
+    source.lang_key.clone_from(&args.lang_key);
+    source.preprocessor = preprocessor.clone();
+
+    let (search, vocabulary, document_ids): (Box<dyn Searcher>, _, _) = if args.streaming {
+        let options = StreamingOptions {
+            chunk_bytes: args.chunk_size_mb * MIB,
+            memory_limit: args.memory_limit_mb.map(|mb| mb * MIB),
+        };
+        let streamed = StreamingIndex::build(&source, options, tokenizer)?;
+        info!(
+            "The total number of files is {} and the alphabet size is {}",
+            streamed.index.len(),
+            streamed.vocabulary.len()
         );
-    }
+        (Box::new(streamed.index), streamed.vocabulary, streamed.document_ids)
+    } else {
+        let (corpus, document_ids) = source.load_with_ids()?;
+        let vocabulary = bm_calc::get_vocabulary(&corpus, tokenizer)?;
+        info!(
+            "The total number of files is {} and the alphabet size is {}",
+            corpus.len(),
+            vocabulary.len()
+        );
+        (Box::new(bm_calc::build_search_engine(&corpus, tokenizer)), vocabulary, document_ids)
+    };
 
-    // Calculate EMD
-    let mut total_work = 0.0;
-    let mut running_sum = 0.0;
+    let (alphabet, report) = vocabulary.prune(filter)?;
+    if filter.is_active() {
+        print_pruning_report(&report);
+    }
+    if tokenizer.config().ngrams > 1 {
+        let counts = ngram_counts(alphabet.iter().map(String::as_str));
+        info!("The alphabet holds {:?} keywords of 1 to {} words", counts, counts.len());
+    }
 
-    // Calculate cumulative difference between distributions
-    for i in 0..num_bins {
-        running_sum += dist1[i] as f64 - dist2[i] as f64;
-        total_work += running_sum.abs();
+    if let Some(document_ids) = &document_ids {
+        info!("Read {} external document IDs, saved next to each run's bins", document_ids.len());
     }
 
-    // Normalize by total number of items
-    total_work / total_items1.max(total_items2) as f64
+    Ok(Engine {
+        search,
+        alphabet,
+        document_ids,
+    })
 }
 
-// Helper function to print comparison stats
-pub fn print_distribution_comparison(bins1: &[HashSet<u32>], bins2: &[HashSet<u32>]) {
-    let sizes1: Vec<usize> = bins1.iter().map(|bin| bin.len()).collect();
-    let sizes2: Vec<usize> = bins2.iter().map(|bin| bin.len()).collect();
-
-    let total_items1: usize = sizes1.iter().sum();
-    let total_items2: usize = sizes2.iter().sum();
-
-    let avg_size1 = total_items1 as f64 / bins1.len() as f64;
-    let avg_size2 = total_items2 as f64 / bins2.len() as f64;
-
-    info!("Distribution comparison:");
-    info!("Distribution 1:");
-    info!("  Total items: {}", total_items1);
-    info!("  Number of bins: {}", bins1.len());
-    info!("  Average bin size: {:.2}", avg_size1);
-    info!("Distribution 2:");
-    info!("  Total items: {}", total_items2);
-    info!("  Number of bins: {}", bins2.len());
-    info!("  Average bin size: {:.2}", avg_size2);
-    info!(
-        "EMD between distributions: {:.4}",
-        calculate_emd(bins1, bins2)
-    );
+/// Runs the sweep subcommand, writes the CSV and prints the Pareto frontier
+///
+/// # Arguments
+/// * `sweep_args` - Ranges from the command line
+/// * `snapshot` - Search results for every keyword
+/// * `base` - Config from the top-level arguments, used for any field without a range
+///
+/// # Errors
+/// Returns error if a run fails or the CSV can't be written
+fn run_sweep(
+    sweep_args: SweepArgs,
+    snapshot: &PostingSnapshot,
+    base: Config,
+) -> Result<()> {
+    let base = Config {
+        save_result: false,
+        ..base
+    };
+
+    let mut grid = SweepGrid::from_base(&base);
+    if let Some(k) = sweep_args.k {
+        grid.k = k.0;
+    }
+    if let Some(d) = sweep_args.d {
+        grid.d = d.0;
+    }
+    if let Some(filter_k) = sweep_args.filter_k {
+        grid.filter_k = filter_k.0;
+    }
+    grid.max_bins = sweep_args.max_bins.0;
+    grid.max_load_factor = sweep_args.max_load_factor.0;
+    grid.min_overlap_factor = sweep_args.min_overlap_factor.0;
+
+    let results = sweep::run_sweep(snapshot, &grid, &base)?;
+    sweep::write_csv(&results, &sweep_args.output)?;
+    info!("Wrote {} sweep results to {}", results.len(), sweep_args.output);
+
+    let (names, frontier): (Vec<_>, Vec<_>) = results
+        .iter()
+        .filter(|result| result.pareto)
+        .map(|result| (sweep::describe_config(&result.config), result.metadata.clone()))
+        .unzip();
+
+    println!("Pareto frontier (total items against max bin load):");
+    print_table(&names, &frontier)
 }
+
@@ -0,0 +1,909 @@
+use crate::bin_index::BinIndex;
+use crate::bm_calc::candidate_bins;
+use crate::error::{BM25Error, Result};
+use crate::hashing::BinHasher;
+use crate::ranking::{Choice, RankingPipeline};
+use crate::Config;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use tracing::trace;
+
+/// Which `PlacementStrategy` `top_k_bins` uses
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlacementKind {
+    /// Drop choices with the removal pipeline (see `Config::removal_pipeline`), then insert into every choice that is left
+    #[default]
+    DChoice,
+    /// Insert into the least loaded of the d choices (greedy power of d choices)
+    LeastLoaded,
+    /// Insert into the choice that already holds the most of the keyword's documents
+    MaxOverlap,
+    /// Insert into a choice with room, evicting keywords to their other choices when all are full
+    /// (see `Config::bin_capacity` and `Config::max_evictions`)
+    Cuckoo,
+    /// Insert into one of the d choices picked at random, as a baseline
+    Random,
+}
+
+impl FromStr for PlacementKind {
+    type Err = BM25Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "d-choice" => Ok(Self::DChoice),
+            "least-loaded" => Ok(Self::LeastLoaded),
+            "max-overlap" => Ok(Self::MaxOverlap),
+            "cuckoo" => Ok(Self::Cuckoo),
+            "random" => Ok(Self::Random),
+            _ => Err(BM25Error::Placement(format!("unknown placement strategy {s}"))),
+        }
+    }
+}
+
+impl fmt::Display for PlacementKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::DChoice => "d-choice",
+            Self::LeastLoaded => "least-loaded",
+            Self::MaxOverlap => "max-overlap",
+            Self::Cuckoo => "cuckoo",
+            Self::Random => "random",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl PlacementKind {
+    /// Creates the strategy for a run
+    ///
+    /// # Arguments
+    /// * `config` - The run's config. `DChoice` reads the removal pipeline, `Cuckoo` the capacity and eviction
+    ///   limit, and `Random` the seed
+    pub fn strategy(self, config: &Config) -> Box<dyn PlacementStrategy> {
+        match self {
+            Self::DChoice => Box::new(DChoice {
+                removal: config.removal_pipeline(),
+            }),
+            Self::LeastLoaded => Box::new(LeastLoaded),
+            Self::MaxOverlap => Box::new(MaxOverlap),
+            Self::Cuckoo => Box::new(Cuckoo {
+                capacity: config.bin_capacity,
+                max_evictions: config.max_evictions,
+            }),
+            Self::Random => Box::new(RandomChoice {
+                rng: StdRng::seed_from_u64(config.seed),
+            }),
+        }
+    }
+}
+
+/// The order `top_k_bins` places keywords in. Online strategies such as `least-loaded` see the loads left
+/// by the keywords before, so the order changes the result
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeywordOrder {
+    /// Sorted by keyword
+    #[default]
+    Alphabet,
+    /// Keywords with the most results first, then by keyword
+    PostingSize,
+    /// Shuffled with `Config::seed`
+    Random,
+}
+
+impl FromStr for KeywordOrder {
+    type Err = BM25Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "alphabet" => Ok(Self::Alphabet),
+            "posting-size" => Ok(Self::PostingSize),
+            "random" => Ok(Self::Random),
+            _ => Err(BM25Error::Placement(format!("unknown keyword order {s}"))),
+        }
+    }
+}
+
+impl fmt::Display for KeywordOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Alphabet => "alphabet",
+            Self::PostingSize => "posting-size",
+            Self::Random => "random",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl KeywordOrder {
+    /// Puts keywords into this order
+    ///
+    /// # Arguments
+    /// * `keywords` - Keywords with their documents
+    /// * `seed` - Seed for the random order
+    pub fn arrange<K: Ord>(self, keywords: &mut [(K, HashSet<u32>)], seed: u64) {
+        keywords.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        match self {
+            Self::Alphabet => {}
+            Self::PostingSize => keywords.sort_by_key(|(_, documents)| std::cmp::Reverse(documents.len())),
+            Self::Random => keywords.shuffle(&mut StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+/// Where a strategy put a keyword
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Placement {
+    /// The bins the keyword's documents were inserted into
+    pub bins: Vec<usize>,
+    /// Documents the keyword shared with the fullest-overlapping chosen bin, i.e. entries that did not need
+    /// to be stored again. Summed into `Metadata::removed_items`
+    pub overlap: usize,
+}
+
+/// Decides which bins each keyword's documents go into.
+///
+/// `top_k_bins` calls `place` once per keyword, in the order set by `Config::order`, and inserts the keyword into the bins
+/// it returns. Strategies that move keywords placed earlier (such as cuckoo) do so through `state`.
+pub trait PlacementStrategy {
+    /// Chooses the bins for a keyword
+    ///
+    /// # Arguments
+    /// * `keyword` - The keyword being placed
+    /// * `documents` - Its top-k document IDs
+    /// * `state` - The bins as they are now, and the speculative pre-pass loads
+    ///
+    /// # Errors
+    /// Returns error if the strategy can't place the keyword
+    fn place(&mut self, keyword: &str, documents: &HashSet<u32>, state: &mut BinState) -> Result<Placement>;
+}
+
+/// A keyword that has been placed, so it can be moved later
+#[derive(Clone, Debug)]
+struct Placed {
+    /// Its document IDs
+    documents: Vec<u32>,
+    /// The bins it's in
+    bins: Vec<usize>,
+}
+
+/// The state of the bins while `top_k_bins` is placing keywords.
+///
+/// Bins count how many placed keywords hold each document, so a keyword can be taken back out without
+/// losing documents other keywords in the bin share.
+pub struct BinState {
+    /// Number of hash choices
+    d: usize,
+    /// Number of bins
+    max_bins: usize,
+    /// Maps keyword choices to bins
+    hasher: Box<dyn BinHasher>,
+    /// Every keyword's documents written into all d of its choices, before anything is placed
+    projected: Vec<Vec<u32>>,
+    /// Document ID to the number of keywords in the bin holding it
+    bins: Vec<HashMap<u32, usize>>,
+    /// Keywords in each bin, in the order they were inserted
+    members: Vec<Vec<String>>,
+    /// Every placed keyword
+    placed: HashMap<String, Placed>,
+    /// Keywords that couldn't be placed in any of their bins
+    stash: BTreeSet<String>,
+    /// Number of times a keyword was moved out of a bin to make room
+    evictions: usize,
+}
+
+impl BinState {
+    /// Creates empty bins and runs the speculative pre-pass over every keyword that will be placed
+    ///
+    /// # Arguments
+    /// * `d` - Number of hash choices
+    /// * `max_bins` - Number of bins
+    /// * `hasher` - Maps keyword choices to bins (see `Config::hash`)
+    /// * `keywords` - The keywords with their document IDs
+    pub fn new<'a>(
+        d: usize,
+        max_bins: usize,
+        hasher: Box<dyn BinHasher>,
+        keywords: impl IntoIterator<Item = (&'a str, &'a HashSet<u32>)>,
+    ) -> Self {
+        let mut projected = vec![Vec::new(); max_bins];
+        for (word, documents) in keywords {
+            for index in candidate_bins(word, d, max_bins, hasher.as_ref()) {
+                projected[index].extend(documents.iter().copied());
+            }
+        }
+
+        Self {
+            d,
+            max_bins,
+            hasher,
+            projected,
+            bins: vec![HashMap::new(); max_bins],
+            members: vec![Vec::new(); max_bins],
+            placed: HashMap::new(),
+            stash: BTreeSet::new(),
+            evictions: 0,
+        }
+    }
+
+    /// The keyword's d candidate bins, see `bm_calc::candidate_bins`
+    pub fn candidates(&self, keyword: &str) -> Vec<usize> {
+        candidate_bins(keyword, self.d, self.max_bins, self.hasher.as_ref())
+    }
+
+    /// The number of distinct documents in a bin
+    pub fn load(&self, bin: usize) -> usize {
+        self.bins[bin].len()
+    }
+
+    /// What a bin's load would be after adding `documents` to it
+    pub fn load_with<'a>(&self, bin: usize, documents: impl IntoIterator<Item = &'a u32>) -> usize {
+        self.load(bin)
+            + documents
+                .into_iter()
+                .filter(|id| !self.bins[bin].contains_key(id))
+                .count()
+    }
+
+    /// How many of `documents` are already in a bin
+    pub fn overlap(&self, bin: usize, documents: &HashSet<u32>) -> usize {
+        documents.iter().filter(|id| self.bins[bin].contains_key(id)).count()
+    }
+
+    /// How many of a keyword's documents other keywords in its bin also hold
+    pub fn shared(&self, bin: usize, keyword: &str) -> usize {
+        self.documents_of(keyword)
+            .iter()
+            .filter(|id| self.bins[bin].get(id).is_some_and(|&count| count > 1))
+            .count()
+    }
+
+    /// The size of a bin in the pre-pass, where every keyword was written into all d choices
+    pub fn projected_load(&self, bin: usize) -> usize {
+        self.projected[bin].len()
+    }
+
+    /// How many entries other keywords wrote into a bin in the pre-pass that are among `documents`
+    pub fn projected_overlap(&self, bin: usize, documents: &HashSet<u32>) -> usize {
+        let overlap = self.projected[bin]
+            .iter()
+            .filter(|&id| documents.contains(id))
+            .count();
+        // minus the keyword's own insertions
+        overlap.saturating_sub(documents.len())
+    }
+
+    /// Average number of entries per bin if every keyword went into a single bin without sharing documents
+    pub fn average_load(&self) -> usize {
+        let total: usize = self.projected.iter().map(Vec::len).sum();
+        (total / self.d.max(1)).div_ceil(self.max_bins.max(1))
+    }
+
+    /// The keywords in a bin, in the order they were inserted
+    pub fn members(&self, bin: usize) -> &[String] {
+        &self.members[bin]
+    }
+
+    /// The bins a keyword is currently in
+    pub fn bins_of(&self, keyword: &str) -> &[usize] {
+        self.placed.get(keyword).map_or(&[], |placed| &placed.bins)
+    }
+
+    /// The documents of a keyword that has been placed or stashed
+    pub fn documents_of(&self, keyword: &str) -> &[u32] {
+        self.placed.get(keyword).map_or(&[], |placed| &placed.documents)
+    }
+
+    /// Records a keyword's documents the first time it is seen
+    fn record<'a>(
+        placed: &'a mut HashMap<String, Placed>,
+        keyword: &str,
+        documents: impl IntoIterator<Item = u32>,
+    ) -> &'a mut Placed {
+        let placed = placed
+            .entry(keyword.to_string())
+            .or_insert_with(|| Placed {
+                documents: Vec::new(),
+                bins: Vec::new(),
+            });
+        if placed.documents.is_empty() {
+            placed.documents = documents.into_iter().collect();
+        }
+        placed
+    }
+
+    /// Inserts a keyword's documents into a bin. Keywords seen before keep the documents they were first given,
+    /// and inserting a keyword into a bin it is already in does nothing
+    pub fn insert(&mut self, bin: usize, keyword: &str, documents: impl IntoIterator<Item = u32>) {
+        self.stash.remove(keyword);
+        let placed = Self::record(&mut self.placed, keyword, documents);
+        if placed.bins.contains(&bin) {
+            return;
+        }
+        placed.bins.push(bin);
+        for &id in &placed.documents {
+            *self.bins[bin].entry(id).or_default() += 1;
+        }
+        self.members[bin].push(keyword.to_string());
+    }
+
+    /// Takes a keyword back out of a bin, keeping documents other keywords in the bin still hold
+    pub fn remove(&mut self, bin: usize, keyword: &str) {
+        let Some(placed) = self.placed.get_mut(keyword) else {
+            return;
+        };
+        let Some(position) = placed.bins.iter().position(|&b| b == bin) else {
+            return;
+        };
+        placed.bins.remove(position);
+        for id in &placed.documents {
+            if let Some(count) = self.bins[bin].get_mut(id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.bins[bin].remove(id);
+                }
+            }
+        }
+        self.members[bin].retain(|member| member != keyword);
+    }
+
+    /// Takes a keyword out of a bin to make room, counting the eviction
+    pub fn evict(&mut self, bin: usize, keyword: &str) {
+        self.remove(bin, keyword);
+        self.evictions += 1;
+    }
+
+    /// Moves a keyword out of all its bins and into the stash
+    pub fn stash(&mut self, keyword: &str, documents: impl IntoIterator<Item = u32>) {
+        let bins = Self::record(&mut self.placed, keyword, documents).bins.clone();
+        for bin in bins {
+            self.remove(bin, keyword);
+        }
+        self.stash.insert(keyword.to_string());
+    }
+
+    /// The number of keywords in the stash
+    pub fn stash_len(&self) -> usize {
+        self.stash.len()
+    }
+
+    /// The stashed keywords with their documents
+    pub fn stashed_documents(&self) -> BTreeMap<String, Vec<u32>> {
+        self.stash
+            .iter()
+            .map(|keyword| (keyword.clone(), self.documents_of(keyword).to_vec()))
+            .collect()
+    }
+
+    /// Inserts a keyword into the chosen bins that have room for it. If none do, it goes into the least loaded
+    /// of its other choices with room, or the stash when there is none
+    ///
+    /// # Arguments
+    /// * `keyword` - The keyword being placed
+    /// * `documents` - Its document IDs
+    /// * `bins` - The bins a `PlacementStrategy` chose
+    /// * `capacity` - Most documents a bin may hold, or `None` to insert into every chosen bin
+    ///
+    /// # Returns
+    /// * `Vec<usize>` - The bins the keyword went into, empty if it was stashed
+    pub fn insert_within(
+        &mut self,
+        keyword: &str,
+        documents: &HashSet<u32>,
+        bins: &[usize],
+        capacity: Option<usize>,
+    ) -> Vec<usize> {
+        let Some(capacity) = capacity else {
+            for &bin in bins {
+                self.insert(bin, keyword, documents.iter().copied());
+            }
+            return bins.to_vec();
+        };
+
+        let mut inserted = Vec::new();
+        for &bin in bins {
+            if self.load_with(bin, documents) <= capacity {
+                self.insert(bin, keyword, documents.iter().copied());
+                inserted.push(bin);
+            } else {
+                trace!("{} doesn't fit in bin {}", keyword, bin);
+            }
+        }
+        if !inserted.is_empty() || bins.is_empty() {
+            return inserted;
+        }
+
+        let fallback = self
+            .candidates(keyword)
+            .into_iter()
+            .filter(|&bin| self.load_with(bin, documents) <= capacity)
+            .min_by_key(|&bin| self.load(bin));
+        if let Some(bin) = fallback {
+            self.insert(bin, keyword, documents.iter().copied());
+            return vec![bin];
+        }
+
+        self.stash(keyword, documents.iter().copied());
+        Vec::new()
+    }
+
+    /// The number of evictions so far
+    pub const fn evictions(&self) -> usize {
+        self.evictions
+    }
+
+    /// Consumes the state, returning the bins and the index of where each keyword ended up
+    pub fn into_parts(self) -> (Vec<HashSet<u32>>, BinIndex) {
+        let bins = self
+            .bins
+            .into_iter()
+            .map(|bin| bin.into_keys().collect())
+            .collect();
+        let mut index = BinIndex::new();
+        for (keyword, placed) in self.placed {
+            if !self.stash.contains(&keyword) {
+                index.insert(keyword, placed.bins);
+            }
+        }
+        for keyword in self.stash {
+            index.insert_stashed(keyword);
+        }
+        (bins, index)
+    }
+}
+
+/// Computes the d candidate bins for a keyword, with each bin's load and overlap in the pre-pass
+fn get_bins(word: &str, documents: &HashSet<u32>, state: &BinState) -> Vec<Choice> {
+    let mut bin_choices = Vec::with_capacity(state.d);
+
+    // Try d different hash functions
+    for (choice, index) in state.candidates(word).into_iter().enumerate() {
+        let overlap = state.projected_overlap(index, documents);
+        let bin_size = state.projected_load(index);
+
+        trace!(
+            "Got index {}, overlap: {}, k: {}, bin size: {}",
+            index,
+            overlap,
+            documents.len(),
+            bin_size
+        );
+
+        bin_choices.push(Choice {
+            choice,
+            bin: index,
+            load: bin_size,
+            overlap,
+        });
+    }
+
+    bin_choices
+}
+
+/// The original `top_k_bins` policy. Choices are ranked on the speculative pre-pass, some are dropped by
+/// the removal pipeline, and the keyword goes into all the rest
+pub struct DChoice {
+    /// Which choices to drop (see `Config::removal_pipeline`)
+    pub removal: RankingPipeline,
+}
+
+impl PlacementStrategy for DChoice {
+    fn place(&mut self, keyword: &str, documents: &HashSet<u32>, state: &mut BinState) -> Result<Placement> {
+        let bin_choices = self.removal.apply(get_bins(keyword, documents, state));
+
+        Ok(Placement {
+            overlap: bin_choices.iter().map(|choice| choice.overlap).max().unwrap_or(0),
+            bins: bin_choices.into_iter().map(|choice| choice.bin).collect(),
+        })
+    }
+}
+
+/// Puts the keyword in the choice with the fewest documents right now. Ties go to the earlier hash choice
+pub struct LeastLoaded;
+
+impl PlacementStrategy for LeastLoaded {
+    fn place(&mut self, keyword: &str, documents: &HashSet<u32>, state: &mut BinState) -> Result<Placement> {
+        let bin = state
+            .candidates(keyword)
+            .into_iter()
+            .min_by_key(|&bin| state.load(bin));
+        Ok(single(bin, documents, state))
+    }
+}
+
+/// Puts the keyword in the choice that already holds most of its documents. Ties go to the less loaded bin,
+/// then the earlier hash choice
+pub struct MaxOverlap;
+
+impl PlacementStrategy for MaxOverlap {
+    fn place(&mut self, keyword: &str, documents: &HashSet<u32>, state: &mut BinState) -> Result<Placement> {
+        let candidates = state.candidates(keyword);
+        let bin = candidates
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by_key(|&(choice, bin)| {
+                (
+                    state.overlap(bin, documents),
+                    std::cmp::Reverse(state.load(bin)),
+                    std::cmp::Reverse(choice),
+                )
+            })
+            .map(|(_, bin)| bin);
+        Ok(single(bin, documents, state))
+    }
+}
+
+/// Cuckoo hashing over the d choices, keeping every bin within a capacity.
+///
+/// A keyword goes into the least loaded choice it fits in. If none has room it goes into its first choice and
+/// the keywords that have been there longest are evicted to their next choice, which may evict others in turn.
+/// Once `max_evictions` is used up for a keyword, whatever is still homeless goes to the stash.
+pub struct Cuckoo {
+    /// Most documents a bin may hold, or `None` for the average load
+    pub capacity: Option<usize>,
+    /// Longest chain of evictions followed when placing one keyword
+    pub max_evictions: usize,
+}
+
+impl Cuckoo {
+    /// The least loaded of a keyword's choices that has room for it, skipping `exclude`
+    fn fits(state: &BinState, keyword: &str, capacity: usize, exclude: Option<usize>) -> Option<usize> {
+        let documents = state.documents_of(keyword);
+        state
+            .candidates(keyword)
+            .into_iter()
+            .filter(|&bin| Some(bin) != exclude && state.load_with(bin, documents) <= capacity)
+            .min_by_key(|&bin| state.load(bin))
+    }
+
+    /// Evicts the oldest keywords not in `keep` from a bin until it is within capacity
+    ///
+    /// # Returns
+    /// * `bool` - Whether the bin is within capacity. False if the eviction count reached `limit` first
+    fn make_room(
+        state: &mut BinState,
+        bin: usize,
+        keep: &[&str],
+        capacity: usize,
+        limit: usize,
+        homeless: &mut VecDeque<(String, usize)>,
+    ) -> bool {
+        while state.load(bin) > capacity {
+            if state.evictions >= limit {
+                return false;
+            }
+            let Some(evicted) = state
+                .members(bin)
+                .iter()
+                .find(|member| !keep.contains(&member.as_str()))
+                .cloned()
+            else {
+                return false;
+            };
+            trace!("Evicting {} from bin {}", evicted, bin);
+            state.evict(bin, &evicted);
+            homeless.push_back((evicted, bin));
+        }
+        true
+    }
+}
+
+impl PlacementStrategy for Cuckoo {
+    fn place(&mut self, keyword: &str, documents: &HashSet<u32>, state: &mut BinState) -> Result<Placement> {
+        let capacity = self.capacity.unwrap_or_else(|| state.average_load());
+        let candidates = state.candidates(keyword);
+        BinState::record(&mut state.placed, keyword, documents.iter().copied());
+
+        if let Some(bin) = Self::fits(state, keyword, capacity, None) {
+            return Ok(single(Some(bin), documents, state));
+        }
+        if candidates.is_empty() || documents.len() > capacity {
+            state.stash(keyword, []);
+            return Ok(Placement::default());
+        }
+
+        // Every choice is full, so make room in the first one
+        let bin = candidates[0];
+        let evictions = state.evictions;
+        let limit = evictions + self.max_evictions;
+
+        state.insert(bin, keyword, []);
+        let mut homeless = VecDeque::new();
+        if !Self::make_room(state, bin, &[keyword], capacity, limit, &mut homeless) {
+            // Out of evictions: put back what was moved and stash the new keyword instead. Nothing ended up
+            // moving, so the evictions don't count either
+            state.stash(keyword, []);
+            for (evicted, from) in std::mem::take(&mut homeless) {
+                state.insert(from, &evicted, []);
+            }
+            state.evictions = evictions;
+            return Ok(Placement::default());
+        }
+
+        while let Some((evicted, from)) = homeless.pop_front() {
+            if let Some(bin) = Self::fits(state, &evicted, capacity, Some(from)) {
+                state.insert(bin, &evicted, []);
+                continue;
+            }
+
+            let choices = state.candidates(&evicted);
+            let position = choices.iter().position(|&b| b == from).unwrap_or(0);
+            let next = choices[(position + 1) % choices.len()];
+            if next == from || state.evictions >= limit {
+                state.stash(&evicted, []);
+                continue;
+            }
+
+            // The keyword being placed stays put, so the bin returned for it is still the one it's in
+            state.insert(next, &evicted, []);
+            if !Self::make_room(state, next, &[&evicted, keyword], capacity, limit, &mut homeless) {
+                state.stash(&evicted, []);
+            }
+        }
+
+        // Only documents that are still shared once the chain is done were saved
+        Ok(Placement {
+            bins: vec![bin],
+            overlap: state.shared(bin, keyword),
+        })
+    }
+}
+
+/// Puts the keyword in one of its choices picked uniformly at random
+pub struct RandomChoice {
+    /// Seeded from `Config::seed` so runs can be repeated
+    rng: StdRng,
+}
+
+impl PlacementStrategy for RandomChoice {
+    fn place(&mut self, keyword: &str, documents: &HashSet<u32>, state: &mut BinState) -> Result<Placement> {
+        let candidates = state.candidates(keyword);
+        let bin = (!candidates.is_empty()).then(|| candidates[self.rng.gen_range(0..candidates.len())]);
+        Ok(single(bin, documents, state))
+    }
+}
+
+/// A placement into a single bin, or none if the keyword has no candidates
+fn single(bin: Option<usize>, documents: &HashSet<u32>, state: &BinState) -> Placement {
+    bin.map_or_else(Placement::default, |bin| Placement {
+        bins: vec![bin],
+        overlap: state.overlap(bin, documents),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::PaddedDatabase;
+
+    #[test]
+    fn strategies_place_in_candidates() {
+        let keywords: Vec<(String, HashSet<u32>)> = (0..50)
+            .map(|i| (format!("word{i}"), (i..i + 5).collect()))
+            .collect();
+        let config = Config {
+            d: 3,
+            max_bins: 8,
+            ..Config::default()
+        };
+
+        for kind in [
+            PlacementKind::LeastLoaded,
+            PlacementKind::MaxOverlap,
+            PlacementKind::Cuckoo,
+            PlacementKind::Random,
+        ] {
+            let mut state = BinState::new(
+                config.d,
+                config.max_bins,
+                config.hash.hasher(&config).unwrap(),
+                keywords.iter().map(|(word, documents)| (word.as_str(), documents)),
+            );
+            let mut strategy = kind.strategy(&config);
+            for (word, documents) in &keywords {
+                let placement = strategy.place(word, documents, &mut state).unwrap();
+                assert_eq!(placement.bins.len(), 1, "{kind} placed {word} in several bins");
+                for &bin in &placement.bins {
+                    state.insert(bin, word, documents.iter().copied());
+                }
+            }
+
+            // Every keyword ends up in exactly one of its own candidates, even after being moved, or the stash
+            for (word, _) in &keywords {
+                let bins = state.bins_of(word);
+                assert!(bins.len() == 1 || (kind == PlacementKind::Cuckoo && bins.is_empty()));
+                assert!(bins.iter().all(|bin| state.candidates(word).contains(bin)));
+            }
+        }
+
+        assert_eq!("least_loaded".parse::<PlacementKind>().unwrap(), PlacementKind::LeastLoaded);
+        assert!("best".parse::<PlacementKind>().is_err());
+    }
+
+    #[test]
+    fn cuckoo_respects_capacity() {
+        let keywords: Vec<(String, HashSet<u32>)> = (0..40)
+            .map(|i| (format!("word{i}"), (i * 3..i * 3 + 4).collect()))
+            .collect();
+        let config = Config {
+            d: 2,
+            max_bins: 8,
+            placement: PlacementKind::Cuckoo,
+            bin_capacity: Some(20),
+            max_evictions: 8,
+            ..Config::default()
+        };
+
+        let mut state = BinState::new(
+            config.d,
+            config.max_bins,
+            config.hash.hasher(&config).unwrap(),
+            keywords.iter().map(|(word, documents)| (word.as_str(), documents)),
+        );
+        let mut strategy = config.placement.strategy(&config);
+        for (word, documents) in &keywords {
+            let placement = strategy.place(word, documents, &mut state).unwrap();
+            for &bin in &placement.bins {
+                state.insert(bin, word, documents.iter().copied());
+            }
+        }
+
+        assert!((0..config.max_bins).all(|bin| state.load(bin) <= 20));
+        assert!(state.evictions() > 0);
+
+        // Every keyword is either in one of its own bins or in the stash
+        let stashed = state.stash_len();
+        let (bins, index) = state.into_parts();
+        assert_eq!(index.stashed().count(), stashed);
+        assert_eq!(index.len() + stashed, keywords.len());
+        for (word, documents) in &keywords {
+            if !index.is_stashed(word) {
+                let placed = index.lookup(word);
+                assert_eq!(placed.len(), 1);
+                assert!(documents.iter().all(|id| bins[placed[0]].contains(id)));
+            }
+        }
+
+        // A chain that runs out of evictions is undone, along with its eviction count
+        let keywords: Vec<(&str, HashSet<u32>)> = vec![
+            ("a", (0..2).collect()),
+            ("b", (2..4).collect()),
+            ("c", (4..7).collect()),
+        ];
+        let config = Config {
+            d: 1,
+            max_bins: 1,
+            bin_capacity: Some(4),
+            max_evictions: 1,
+            ..config
+        };
+        let mut state = BinState::new(
+            config.d,
+            config.max_bins,
+            config.hash.hasher(&config).unwrap(),
+            keywords.iter().map(|(word, documents)| (*word, documents)),
+        );
+        let mut strategy = config.placement.strategy(&config);
+        for (word, documents) in &keywords {
+            let placement = strategy.place(word, documents, &mut state).unwrap();
+            for &bin in &placement.bins {
+                state.insert(bin, word, documents.iter().copied());
+            }
+        }
+        assert_eq!(state.evictions(), 0);
+        assert_eq!(state.load(0), 4);
+        assert_eq!(state.stash_len(), 1);
+    }
+
+    #[test]
+    fn cuckoo_chains_leave_placement_current() {
+        let keywords: Vec<(String, HashSet<u32>)> = (0..120)
+            .map(|i| (format!("w{}_{}", i % 7, i), (i * 3..i * 3 + 1 + i % 5).collect()))
+            .collect();
+
+        for bin_capacity in [Some(6), None] {
+            let config = Config {
+                d: 2,
+                max_bins: 4,
+                placement: PlacementKind::Cuckoo,
+                bin_capacity,
+                max_evictions: 50,
+                ..Config::default()
+            };
+            let mut state = BinState::new(
+                config.d,
+                config.max_bins,
+                config.hash.hasher(&config).unwrap(),
+                keywords.iter().map(|(word, documents)| (word.as_str(), documents)),
+            );
+            let capacity = bin_capacity.unwrap_or_else(|| state.average_load());
+            let mut strategy = config.placement.strategy(&config);
+            for (word, documents) in &keywords {
+                let placement = strategy.place(word, documents, &mut state).unwrap();
+                let current = state.bins_of(word);
+                assert!(current.is_empty() || current == placement.bins, "{word} was moved while being placed");
+                let inserted = state.insert_within(word, documents, &placement.bins, config.bin_capacity);
+                assert_eq!(inserted, placement.bins);
+                assert_eq!(state.bins_of(word), inserted);
+                if let [bin] = inserted[..] {
+                    assert_eq!(placement.overlap, state.shared(bin, word), "{word} counted evicted documents");
+                }
+            }
+
+            assert!(state.evictions() > 0, "{bin_capacity:?}");
+            assert!((0..config.max_bins).all(|bin| state.load(bin) <= capacity), "{bin_capacity:?}");
+            assert!(keywords.iter().all(|(word, _)| state.bins_of(word).len() <= 1));
+        }
+    }
+
+    #[test]
+    fn capacity_bounds_every_strategy() {
+        let keywords: Vec<(String, HashSet<u32>)> = (0..40)
+            .map(|i| (format!("word{i}"), (i * 3..i * 3 + 4).collect()))
+            .collect();
+
+        for placement in [PlacementKind::DChoice, PlacementKind::LeastLoaded, PlacementKind::Random] {
+            let config = Config {
+                d: 2,
+                max_bins: 8,
+                placement,
+                bin_capacity: Some(16),
+                min_overlap_factor: 0,
+                max_load_factor: 0,
+                ..Config::default()
+            };
+            let mut state = BinState::new(
+                config.d,
+                config.max_bins,
+                config.hash.hasher(&config).unwrap(),
+                keywords.iter().map(|(word, documents)| (word.as_str(), documents)),
+            );
+            let mut strategy = config.placement.strategy(&config);
+            for (word, documents) in &keywords {
+                let chosen = strategy.place(word, documents, &mut state).unwrap();
+                let inserted = state.insert_within(word, documents, &chosen.bins, config.bin_capacity);
+                assert_eq!(inserted.is_empty(), state.stashed_documents().contains_key(word.as_str()));
+            }
+
+            assert!((0..config.max_bins).all(|bin| state.load(bin) <= 16), "{placement} overfilled a bin");
+            let stash = state.stashed_documents();
+            assert!(!stash.is_empty());
+            assert!(stash.values().all(|documents| documents.len() == 4));
+
+            let (bins, index) = state.into_parts();
+            assert_eq!(index.len() + stash.len(), keywords.len());
+            let database = PaddedDatabase::pad(&bins, 16, stash).unwrap();
+            assert!(database.bins().iter().all(|bin| bin.len() == 16));
+            assert_eq!(database.real_entries(), bins.iter().map(HashSet::len).sum::<usize>());
+        }
+    }
+
+    #[test]
+    fn keyword_orders() {
+        let mut keywords: Vec<(&str, HashSet<u32>)> = vec![
+            ("b", (0..3).collect()),
+            ("c", (0..5).collect()),
+            ("a", (0..1).collect()),
+            ("d", (0..5).collect()),
+        ];
+
+        KeywordOrder::Alphabet.arrange(&mut keywords, 0);
+        assert_eq!(keywords.iter().map(|k| k.0).collect::<Vec<_>>(), ["a", "b", "c", "d"]);
+        KeywordOrder::PostingSize.arrange(&mut keywords, 0);
+        assert_eq!(keywords.iter().map(|k| k.0).collect::<Vec<_>>(), ["c", "d", "b", "a"]);
+
+        // The same seed gives the same order whatever order the keywords came in
+        KeywordOrder::Random.arrange(&mut keywords, 7);
+        let shuffled: Vec<_> = keywords.iter().map(|k| k.0).collect();
+        keywords.reverse();
+        KeywordOrder::Random.arrange(&mut keywords, 7);
+        assert_eq!(keywords.iter().map(|k| k.0).collect::<Vec<_>>(), shuffled);
+    }
+}
@@ -1,6 +1,7 @@
 use plotters::prelude::*;
 use std::collections::HashSet;
 use tabled::{Table, Tabled};
+use tracing::info;
 
 #[derive(Tabled)]
 #[allow(clippy::missing_docs_in_private_items)]
@@ -10,14 +11,41 @@ struct ExperimentResult {
     name: String,
     #[tabled(rename = "# Bins")]
     bins: usize,
+    #[tabled(rename = "Removal")]
+    removal: String,
     #[tabled(rename = "Items Removed")]
     removed: usize,
     #[tabled(rename = "Total Items")]
     total: usize,
     #[tabled(rename = "Avg Load")]
     avg_load: usize,
+    #[tabled(rename = "Max Load")]
+    max_load: usize,
+    #[tabled(rename = "Keywords (1/2/3-grams)")]
+    composition: String,
     #[tabled(rename = "Keywords w/Overlap")]
     keywords: usize,
+    #[tabled(rename = "Stash")]
+    stash: usize,
+    #[tabled(rename = "Evictions")]
+    evictions: usize,
+    #[tabled(rename = "Capacity")]
+    capacity: usize,
+    #[tabled(rename = "Padding Overhead")]
+    padding_overhead: String,
+    #[tabled(rename = "Real Fraction")]
+    real_fraction: String,
+    #[tabled(rename = "Recall")]
+    recall: String,
+    #[tabled(rename = "Precision")]
+    precision: String,
+    #[tabled(rename = "False Positives")]
+    false_positives: String,
+}
+
+/// Formats an optional value for the table, showing "-" when it wasn't calculated
+fn or_dash<T: std::fmt::Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_string(), |v| v.to_string())
 }
 
 use crate::error::Result;
@@ -73,7 +101,7 @@ pub fn fullness_histogram(
     root.fill(&WHITE).unwrap();
 
     let mut chart = ChartBuilder::on(&root)
-        .caption(&title, ("sans-serif", 40))
+        .caption(title, ("sans-serif", 40))
         .margin(5)
         .x_label_area_size(30)
         .y_label_area_size(30)
@@ -123,10 +151,26 @@ pub fn print_table(
         results.push(ExperimentResult {
             name: name.clone(),
             bins: meta.num_bins,
+            removal: meta.removal.clone(),
             removed: meta.removed_items,
             total: meta.total_items,
             avg_load: meta.average_load_per_bin,
+            max_load: meta.max_load,
+            composition: meta
+                .keyword_ngrams
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join("/"),
             keywords: meta.keywords_with_overlap,
+            stash: meta.stash_size,
+            evictions: meta.evictions,
+            capacity: meta.capacity,
+            padding_overhead: format!("{:.4}", meta.padding_overhead),
+            real_fraction: format!("{:.4}", meta.real_fraction),
+            recall: or_dash(meta.recall.map(|r| format!("{r:.4}"))),
+            precision: or_dash(meta.precision.map(|p| format!("{p:.4}"))),
+            false_positives: or_dash(meta.false_positives),
         });
     }
 
@@ -135,3 +179,154 @@ pub fn print_table(
 
     Ok(())
 }
+
+/// Prints a hash diagnostics report as a two column table
+///
+/// # Arguments
+///
+/// * `diagnostics`: Output of `HashDiagnostics::compute`
+pub fn print_hash_report(diagnostics: &crate::diagnostics::HashDiagnostics) {
+    let rows = [
+        ("Keywords", diagnostics.keywords.to_string()),
+        ("Choices (d)", diagnostics.d.to_string()),
+        ("Bins", diagnostics.max_bins.to_string()),
+        ("Expected hits per bin", format!("{:.2}", diagnostics.expected)),
+        (
+            "Observed hits per bin (min/max)",
+            format!("{}/{}", diagnostics.min_observed(), diagnostics.max_observed()),
+        ),
+        (
+            "Chi-square (degrees of freedom)",
+            format!("{:.2} ({})", diagnostics.chi_square, diagnostics.degrees_of_freedom),
+        ),
+        ("p-value", format!("{:.4}", diagnostics.p_value)),
+        (
+            "Choice collision rate (expected)",
+            format!("{:.4} ({:.4})", diagnostics.collision_rate, diagnostics.expected_collision_rate),
+        ),
+        ("Repeated draws resampled", diagnostics.repeated.to_string()),
+        ("Biased draws rejected", diagnostics.rejected.to_string()),
+    ];
+    print_measures(&rows);
+}
+
+/// Prints a summary of a query workload run against the bins
+///
+/// # Arguments
+/// * `evaluation` - Result of `queries::evaluate_queries`
+pub fn print_query_report(evaluation: &crate::queries::QueryEvaluation) {
+    let answerable = evaluation.queries.iter().filter(|q| q.relevant > 0).count();
+    let relevant: usize = evaluation.queries.iter().map(|q| q.relevant).sum();
+    let rows = [
+        ("Queries", evaluation.queries.len().to_string()),
+        ("Queries with results", answerable.to_string()),
+        ("Queries without keywords", evaluation.without_keywords.to_string()),
+        ("Mean recall of the bins", format!("{:.4}", evaluation.mean_recall)),
+        ("Mean recall of keyword top-k", format!("{:.4}", evaluation.mean_keyword_recall)),
+        ("Top-k documents", relevant.to_string()),
+        ("Missed: in no keyword's top-k", evaluation.missed_by_keywords.to_string()),
+        ("Missed: lost by binning", evaluation.missed_by_bins.to_string()),
+    ];
+    print_measures(&rows);
+}
+
+/// Prints how many keywords each vocabulary filter removed
+///
+/// # Arguments
+/// * `report` - Returned by `vocabulary::Vocabulary::prune`
+pub fn print_pruning_report(report: &crate::vocabulary::PruningReport) {
+    let rows = [
+        ("Keywords", report.keywords.to_string()),
+        ("Removed: deny-list", report.denied.to_string()),
+        ("Removed: below min document frequency", report.below_min_df.to_string()),
+        ("Removed: above max document frequency", report.above_max_df.to_string()),
+        ("Removed: weak phrases", report.weak_phrases.to_string()),
+        ("Removed: most frequent", report.most_frequent.to_string()),
+        ("Removed: over the keyword cap", report.over_cap.to_string()),
+        ("Keywords kept", report.kept.to_string()),
+    ];
+    print_measures(&rows);
+}
+
+/// Prints a two-column table of named measures
+fn print_measures(rows: &[(&str, String)]) {
+    let mut builder = tabled::builder::Builder::default();
+    builder.push_record(["Measure", "Value"]);
+    for (name, value) in rows {
+        builder.push_record([name.to_string(), value.clone()]);
+    }
+    println!("{}", builder.build());
+}
+
+/// Calculates the earth mover's distance between the bin size distributions of two sets of bins
+///
+/// # Arguments
+///
+/// * `bins1`: The first set of bins
+/// * `bins2`: The second set of bins
+///
+/// returns: f64, the EMD normalised by the total number of items
+pub fn calculate_emd(bins1: &[HashSet<u32>], bins2: &[HashSet<u32>]) -> f64 {
+    // Get distributions (number of items in each bin)
+    let mut dist1: Vec<usize> = bins1.iter().map(|bin| bin.len()).collect();
+    let mut dist2: Vec<usize> = bins2.iter().map(|bin| bin.len()).collect();
+
+    // Sort the distributions to minimize total distance
+    dist1.sort_unstable();
+    dist2.sort_unstable();
+
+    // Make sure distributions have same length
+    let num_bins = dist1.len().max(dist2.len());
+    dist1.resize(num_bins, 0);
+    dist2.resize(num_bins, 0);
+
+    // Calculate total items (should be same in both distributions)
+    let total_items1: usize = dist1.iter().sum();
+    let total_items2: usize = dist2.iter().sum();
+
+    if total_items1 != total_items2 {
+        println!(
+            "Warning: Distributions have different total items: {} vs {}",
+            total_items1, total_items2
+        );
+    }
+
+    // Calculate EMD
+    let mut total_work = 0.0;
+    let mut running_sum = 0.0;
+
+    // Calculate cumulative difference between distributions
+    for i in 0..num_bins {
+        running_sum += dist1[i] as f64 - dist2[i] as f64;
+        total_work += running_sum.abs();
+    }
+
+    // Normalize by total number of items
+    total_work / total_items1.max(total_items2) as f64
+}
+
+/// Helper function to print comparison stats of two sets of bins, including their EMD
+pub fn print_distribution_comparison(bins1: &[HashSet<u32>], bins2: &[HashSet<u32>]) {
+    let sizes1: Vec<usize> = bins1.iter().map(|bin| bin.len()).collect();
+    let sizes2: Vec<usize> = bins2.iter().map(|bin| bin.len()).collect();
+
+    let total_items1: usize = sizes1.iter().sum();
+    let total_items2: usize = sizes2.iter().sum();
+
+    let avg_size1 = total_items1 as f64 / bins1.len() as f64;
+    let avg_size2 = total_items2 as f64 / bins2.len() as f64;
+
+    info!("Distribution comparison:");
+    info!("Distribution 1:");
+    info!("  Total items: {}", total_items1);
+    info!("  Number of bins: {}", bins1.len());
+    info!("  Average bin size: {:.2}", avg_size1);
+    info!("Distribution 2:");
+    info!("  Total items: {}", total_items2);
+    info!("  Number of bins: {}", bins2.len());
+    info!("  Average bin size: {:.2}", avg_size2);
+    info!(
+        "EMD between distributions: {:.4}",
+        calculate_emd(bins1, bins2)
+    );
+}
@@ -0,0 +1,186 @@
+use crate::error::{BM25Error, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+
+/// Script and style elements, whose contents aren't text
+const SCRIPT_PATTERN: &str = r"(?is)<(?:script|style)\b.*?</(?:script|style)\s*>";
+/// Comments, then any other tag
+const MARKUP_PATTERN: &str = r"(?s)<!--.*?-->|<[^>]*>";
+/// Web addresses with a scheme or starting with `www.`
+const URL_PATTERN: &str = r"(?i)\b(?:https?://|ftp://|www\.)\S+";
+/// Email addresses
+const EMAIL_PATTERN: &str = r"(?i)\b[a-z0-9._%+-]+@[a-z0-9.-]+\.[a-z]{2,}\b";
+/// Numbers, with thousands separators and decimals
+const NUMBER_PATTERN: &str = r"\d+[,\d]*\.?\d*";
+
+/// A regex replacement applied to every document
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Substitution {
+    /// The regex to match
+    pub pattern: String,
+    /// What matches are replaced with. `$1` or `${name}` insert capture groups
+    pub replacement: String,
+}
+
+impl FromStr for Substitution {
+    type Err = BM25Error;
+
+    /// Parses `pattern=>replacement`, where the replacement may be empty
+    fn from_str(s: &str) -> Result<Self> {
+        let (pattern, replacement) = s
+            .split_once("=>")
+            .ok_or_else(|| BM25Error::Preprocess(format!("substitution {s} is not of the form pattern=>replacement")))?;
+        Ok(Self {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+        })
+    }
+}
+
+/// Which cleaning stages run on each document's text before it is tokenized. All are off by default
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct PreprocessConfig {
+    /// Drop HTML/XML tags, comments, scripts and styles, and decode entities like `&amp;`
+    pub strip_html: bool,
+    /// Apply Unicode NFKC normalization, so ligatures, full-width forms and the like match their plain forms
+    pub nfkc: bool,
+    /// Drop web addresses
+    pub remove_urls: bool,
+    /// Drop email addresses
+    pub remove_emails: bool,
+    /// Drop numbers
+    pub remove_numbers: bool,
+    /// Lowercase the text
+    pub lowercase: bool,
+    /// Regex replacements, applied in order after every other stage
+    pub substitutions: Vec<Substitution>,
+}
+
+impl PreprocessConfig {
+    /// Whether any stage is enabled
+    pub fn is_active(&self) -> bool {
+        self != &Self::default()
+    }
+}
+
+/// The stages of a `PreprocessConfig`, with their regexes compiled.
+///
+/// Stages run in the order strip HTML, NFKC, URLs, emails, numbers, lowercase, then substitutions. Removed
+/// text is replaced with a space so the words either side of it stay apart.
+#[derive(Clone, Debug, Default)]
+pub struct Preprocessor {
+    /// Script/style elements and other markup, if HTML is stripped
+    html: Option<(Regex, Regex)>,
+    /// Whether text is NFKC normalized
+    nfkc: bool,
+    /// Patterns replaced with a space: URLs, emails and numbers, in that order
+    removals: Vec<Regex>,
+    /// Whether text is lowercased
+    lowercase: bool,
+    /// Compiled substitutions with their replacements
+    substitutions: Vec<(Regex, String)>,
+}
+
+impl Preprocessor {
+    /// Compiles the enabled stages
+    ///
+    /// # Errors
+    /// Returns error if a substitution's pattern isn't a valid regex
+    pub fn new(config: &PreprocessConfig) -> Result<Self> {
+        let compile = |pattern: &str| {
+            Regex::new(pattern).map_err(|error| BM25Error::Preprocess(format!("invalid pattern {pattern}: {error}")))
+        };
+
+        let html = if config.strip_html {
+            Some((compile(SCRIPT_PATTERN)?, compile(MARKUP_PATTERN)?))
+        } else {
+            None
+        };
+        let removals = [
+            (config.remove_urls, URL_PATTERN),
+            (config.remove_emails, EMAIL_PATTERN),
+            (config.remove_numbers, NUMBER_PATTERN),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, pattern)| compile(pattern))
+        .collect::<Result<_>>()?;
+        let substitutions = config
+            .substitutions
+            .iter()
+            .map(|substitution| Ok((compile(&substitution.pattern)?, substitution.replacement.clone())))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            html,
+            nfkc: config.nfkc,
+            removals,
+            lowercase: config.lowercase,
+            substitutions,
+        })
+    }
+
+    /// Whether any stage runs, so loading can skip the preprocessor when none does
+    pub const fn is_active(&self) -> bool {
+        self.html.is_some() || self.nfkc || !self.removals.is_empty() || self.lowercase || !self.substitutions.is_empty()
+    }
+
+    /// Cleans a document's text with every enabled stage
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        if let Some((scripts, markup)) = &self.html {
+            text = scripts.replace_all(&text, " ").into_owned();
+            text = markup.replace_all(&text, " ").into_owned();
+            text = html_escape::decode_html_entities(&text).into_owned();
+        }
+        if self.nfkc {
+            text = text.nfkc().collect();
+        }
+        for removal in &self.removals {
+            text = removal.replace_all(&text, " ").into_owned();
+        }
+        if self.lowercase {
+            text = text.to_lowercase();
+        }
+        for (pattern, replacement) in &self.substitutions {
+            text = pattern.replace_all(&text, replacement.as_str()).into_owned();
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleans_in_order() {
+        let config = PreprocessConfig {
+            strip_html: true,
+            nfkc: true,
+            remove_urls: true,
+            remove_emails: true,
+            remove_numbers: true,
+            lowercase: true,
+            substitutions: vec!["colour=>color".parse().unwrap(), r"\s+=> ".parse().unwrap()],
+        };
+        let preprocessor = Preprocessor::new(&config).unwrap();
+        let html = "<p>The <b>Colour</b> of ﬁre &amp; ice</p><script>var x = 1;</script>\
+                    <!-- note --> costs 1,299.50 at https://example.com/shop or ask sales@example.com";
+        assert_eq!(preprocessor.apply(html), " the color of fire & ice costs at or ask ");
+
+        let unchanged = Preprocessor::new(&PreprocessConfig::default()).unwrap();
+        assert!(!unchanged.is_active());
+        assert_eq!(unchanged.apply("<b>Hi</b> 42"), "<b>Hi</b> 42");
+
+        assert!("no-arrow".parse::<Substitution>().is_err());
+        let invalid = PreprocessConfig {
+            substitutions: vec!["(=>x".parse().unwrap()],
+            ..PreprocessConfig::default()
+        };
+        assert!(Preprocessor::new(&invalid).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use crate::bin_index::BinIndex;
+use crate::bm_calc::{candidate_bins, search_top_k, Searcher};
+use crate::dataloader::Text;
+use crate::error::{BM25Error, Result};
+use crate::snapshot::PostingSnapshot;
+use crate::tokenizer::KeywordTokenizer;
+use crate::Config;
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use tracing::info;
+
+/// How well the bins answer one multi-word query
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryRecall {
+    /// The query as it appeared in the workload
+    pub query: String,
+    /// The query's distinct tokens
+    pub tokens: Vec<String>,
+    /// Tokens that are keywords in the snapshot, so the client has bins to fetch for them
+    pub keywords: usize,
+    /// Number of documents in the query's true top-k
+    pub relevant: usize,
+    /// Fraction of the true top-k found in the union of the keywords' candidate bins
+    pub recall: f64,
+    /// Fraction of the true top-k in the union of the keywords' own top-k, what a lossless layout guarantees
+    pub keyword_recall: f64,
+    /// Number of distinct documents in the union of the candidate bins
+    pub retrieved: usize,
+    /// Missed top-k documents that are in none of the keywords' top-k
+    pub missed_by_keywords: usize,
+    /// Missed top-k documents in some keyword's top-k, which binning lost
+    pub missed_by_bins: usize,
+}
+
+/// Result of running a query workload against a binned layout
+#[derive(Clone, Debug, Default)]
+pub struct QueryEvaluation {
+    /// Per-query results, in workload order
+    pub queries: Vec<QueryRecall>,
+    /// Mean recall of the bins over queries with a non-empty top-k
+    pub mean_recall: f64,
+    /// Mean recall of the keywords' own top-k over the same queries
+    pub mean_keyword_recall: f64,
+    /// Queries none of whose tokens are keywords
+    pub without_keywords: usize,
+    /// Total missed top-k documents that no keyword's top-k holds
+    pub missed_by_keywords: usize,
+    /// Total missed top-k documents that a keyword's top-k holds but its bins don't
+    pub missed_by_bins: usize,
+}
+
+/// Reads a query workload. Each non-empty line is a query, either plain text or a JSON object with a `text`
+/// or `query` field (as in BEIR's `queries.jsonl`)
+///
+/// # Arguments
+/// * `filename` - The workload
+/// * `lang_key` - Field of a JSON query holding its language, which is kept with the query so a multilingual
+///   tokenizer doesn't have to detect it
+///
+/// # Errors
+/// Returns error if the file cannot be read, or a JSON line is invalid or has no query text
+pub fn load_queries(filename: &str, lang_key: Option<&str>) -> Result<Vec<Text>> {
+    fs::read_to_string(filename)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            if !line.starts_with('{') {
+                return Ok(line.into());
+            }
+            let value: serde_json::Value = serde_json::from_str(line)?;
+            let text = ["text", "query"]
+                .iter()
+                .find_map(|key| value.get(key).and_then(serde_json::Value::as_str))
+                .ok_or_else(|| BM25Error::Corpus(format!("query has no text or query field: {line}")))?;
+            let language = lang_key.and_then(|key| value.get(key)?.as_str());
+            Ok(Text::new(text.to_string(), language))
+        })
+        .collect()
+}
+
+/// Runs every query against the engine and checks its top-k against the bins a client would fetch.
+///
+/// The client tokenizes the query and fetches the d candidate bins of every token that is a keyword, and the whole
+/// stash, as it can't tell whether a keyword was stashed. Bins also hold other keywords' documents, so they can
+/// return more of the top-k than the keywords' own top-k do. Missed documents are split into those no keyword's
+/// top-k holds, which only turn up by chance, and those binning lost.
+///
+/// # Arguments
+/// * `search_engine` - Engine over the same corpus as the snapshot, for the true multi-word top-k
+/// * `queries` - The workload
+/// * `tokenizer` - The tokenizer the alphabet and engine were built with
+/// * `snapshot` - Per-keyword results the bins were built from
+/// * `bins` - Bins returned by `bm_calc::top_k_bins_with_index`
+/// * `index` - The index returned with `bins`, for the keywords that went to the stash
+/// * `config` - The config used to build `bins`
+///
+/// # Errors
+/// Returns error if the snapshot holds fewer than `config.k` results per keyword, or the hash needs a key and
+/// `config` has none
+pub fn evaluate_queries<S: Searcher + ?Sized>(
+    search_engine: &S,
+    queries: &[Text],
+    tokenizer: &KeywordTokenizer,
+    snapshot: &PostingSnapshot,
+    bins: &[HashSet<u32>],
+    index: &BinIndex,
+    config: &Config,
+) -> Result<QueryEvaluation> {
+    let hasher = config.hash.hasher(config)?;
+    let mut evaluation = QueryEvaluation::default();
+
+    let mut stash = HashSet::new();
+    for keyword in index.stashed() {
+        stash.extend(snapshot.results(keyword, config.k)?.iter().map(|posting| posting.id));
+    }
+
+    for query in queries {
+        let query_tokens = tokenizer.tokenize(&query.text, query.language.as_deref());
+        let tokens: Vec<String> = query_tokens.iter().cloned().collect::<BTreeSet<_>>().into_iter().collect();
+
+        let mut retrieved = stash.clone();
+        let mut keyword_results = HashSet::new();
+        let mut keywords = 0;
+        for token in &tokens {
+            let results = snapshot.results(token, config.k)?;
+            if results.len() < config.filter_k.max(1) {
+                continue;
+            }
+            keywords += 1;
+            keyword_results.extend(results.iter().map(|posting| posting.id));
+            for bin in candidate_bins(token, config.d, config.max_bins, hasher.as_ref()) {
+                retrieved.extend(bins[bin].iter().copied());
+            }
+        }
+
+        let relevant: Vec<u32> = search_top_k(search_engine, &query_tokens.join(" "), config.k)
+            .into_iter()
+            .map(|result| result.document.id)
+            .collect();
+        let found = relevant.iter().filter(|id| retrieved.contains(id)).count();
+        let in_keywords = relevant.iter().filter(|id| keyword_results.contains(id)).count();
+        let fraction = |count: usize| {
+            if relevant.is_empty() {
+                1.0
+            } else {
+                count as f64 / relevant.len() as f64
+            }
+        };
+
+        let (lost, missed): (Vec<&u32>, Vec<&u32>) = relevant
+            .iter()
+            .filter(|id| !retrieved.contains(id))
+            .partition(|id| keyword_results.contains(id));
+        let (missed_by_keywords, missed_by_bins) = (missed.len(), lost.len());
+        evaluation.missed_by_keywords += missed_by_keywords;
+        evaluation.missed_by_bins += missed_by_bins;
+        if keywords == 0 {
+            evaluation.without_keywords += 1;
+        }
+
+        evaluation.queries.push(QueryRecall {
+            query: query.text.clone(),
+            tokens,
+            keywords,
+            relevant: relevant.len(),
+            recall: fraction(found),
+            keyword_recall: fraction(in_keywords),
+            retrieved: retrieved.len(),
+            missed_by_keywords,
+            missed_by_bins,
+        });
+    }
+
+    let answerable: Vec<&QueryRecall> = evaluation.queries.iter().filter(|q| q.relevant > 0).collect();
+    let count = answerable.len().max(1) as f64;
+    evaluation.mean_recall = answerable.iter().fold(0.0, |sum, q| sum + q.recall) / count;
+    evaluation.mean_keyword_recall = answerable.iter().fold(0.0, |sum, q| sum + q.keyword_recall) / count;
+
+    info!(
+        "{} queries: mean recall {:.4} against {:.4} for the keywords' own top-k. {} documents were in no keyword's top-k and {} were lost by binning",
+        evaluation.queries.len(),
+        evaluation.mean_recall,
+        evaluation.mean_keyword_recall,
+        evaluation.missed_by_keywords,
+        evaluation.missed_by_bins
+    );
+
+    Ok(evaluation)
+}
+
+impl QueryEvaluation {
+    /// Writes the per-query results to a CSV
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be written
+    pub fn write_csv(&self, filename: &str) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(BufWriter::new(File::create(filename)?));
+        writer.write_record([
+            "query",
+            "tokens",
+            "keywords",
+            "relevant",
+            "recall",
+            "keyword_recall",
+            "retrieved",
+            "missed_by_keywords",
+            "missed_by_bins",
+        ])?;
+        for q in &self.queries {
+            writer.write_record([
+                q.query.clone(),
+                q.tokens.join(" "),
+                q.keywords.to_string(),
+                q.relevant.to_string(),
+                q.recall.to_string(),
+                q.keyword_recall.to_string(),
+                q.retrieved.to_string(),
+                q.missed_by_keywords.to_string(),
+                q.missed_by_bins.to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bm_calc::{build_search_engine, get_alphabet, top_k_bins_with_index};
+
+    #[test]
+    fn multi_word_queries() {
+        let corpus: Vec<Text> = [
+            "Apples, oranges, papayas, and more papayas.",
+            "Papayas and apples fell from the sky.",
+            "The sky blushed pink as the sun dipped below the horizon.",
+            "She found a forgotten letter tucked inside an old book.",
+            "An apple a day keeps the doctor away.",
+        ]
+        .iter()
+        .map(|&s| s.into())
+        .collect();
+        let tokenizer = KeywordTokenizer::default();
+        let alphabet = get_alphabet(&corpus, &tokenizer).unwrap();
+        let search = build_search_engine(&corpus, &tokenizer);
+        let config = Config {
+            k: 2,
+            d: 2,
+            max_bins: 4,
+            max_load_factor: 0,
+            save_result: false,
+            ..Config::default()
+        };
+        let snapshot = PostingSnapshot::build(&search, &alphabet, config.k);
+        let (_, bins, index) = top_k_bins_with_index(&snapshot, config.clone()).unwrap();
+
+        let path = std::env::temp_dir().join("bm25_calc_queries.jsonl");
+        fs::write(&path, "apple sky\n\n{\"_id\": \"q2\", \"text\": \"old letter\", \"lang\": \"en\"}\nzebra\n").unwrap();
+        let queries = load_queries(path.to_str().unwrap(), Some("lang")).unwrap();
+        assert_eq!(queries, ["apple sky".into(), Text::new("old letter".to_string(), Some("en")), "zebra".into()]);
+
+        let evaluation = evaluate_queries(&search, &queries, &tokenizer, &snapshot, &bins, &index, &config).unwrap();
+        assert_eq!(evaluation.queries.len(), 3);
+        assert_eq!(evaluation.without_keywords, 1);
+
+        let first = &evaluation.queries[0];
+        assert_eq!(first.tokens, ["appl", "sky"]);
+        assert_eq!(first.relevant, 2);
+        assert!((first.keyword_recall - 1.0).abs() < 1e-9);
+        for q in &evaluation.queries {
+            let found = (q.recall * q.relevant as f64).round() as usize;
+            assert_eq!(found + q.missed_by_keywords + q.missed_by_bins, q.relevant);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use crate::error::{BM25Error, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// One of a keyword's d hash choices, as seen by the ranking pipeline
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Choice {
+    /// Which hash function produced it, 0 to d - 1
+    pub choice: usize,
+    /// The bin index
+    pub bin: usize,
+    /// Size of the bin in the pre-pass
+    pub load: usize,
+    /// Entries in the bin in the pre-pass that other keywords share with this one
+    pub overlap: usize,
+}
+
+/// What a removal stage ranks choices by
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Criterion {
+    /// Size of the bin
+    Load,
+    /// Number of the keyword's documents other keywords put in the bin
+    Overlap,
+    /// Overlap as a fraction of the bin size
+    OverlapRatio,
+    /// The hash function's position, so `highest` drops the last choices
+    HashOrder,
+}
+
+/// Which end of the ranking a stage removes from
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Remove the choices that rank lowest
+    Lowest,
+    /// Remove the choices that rank highest
+    Highest,
+}
+
+/// A single step of the pipeline: drop `count` choices from one end of a ranking
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RemovalStage {
+    /// What to rank by
+    pub criterion: Criterion,
+    /// Which end to drop
+    pub direction: Direction,
+    /// How many choices to drop
+    pub count: usize,
+}
+
+/// The stages `d-choice` placement runs over a keyword's choices before inserting into whatever is left.
+///
+/// Written as comma separated `criterion:direction:count` stages, e.g. `overlap:lowest:1,load:highest:1`.
+/// Criteria are `load`, `overlap`, `overlap-ratio` and `hash-order`; directions are `lowest` and `highest`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct RankingPipeline(pub Vec<RemovalStage>);
+
+impl Criterion {
+    /// Orders two choices by this criterion, lowest first
+    fn compare(self, a: &Choice, b: &Choice) -> Ordering {
+        match self {
+            Self::Load => a.load.cmp(&b.load),
+            Self::Overlap => a.overlap.cmp(&b.overlap),
+            // a.overlap / a.load against b.overlap / b.load, without dividing
+            Self::OverlapRatio => (a.overlap * b.load.max(1)).cmp(&(b.overlap * a.load.max(1))),
+            Self::HashOrder => a.choice.cmp(&b.choice),
+        }
+    }
+}
+
+impl RemovalStage {
+    /// Drops `count` choices from one end of the ranking. Ties keep the order the choices came in
+    pub fn apply(&self, mut choices: Vec<Choice>) -> Vec<Choice> {
+        match self.direction {
+            Direction::Lowest => choices.sort_by(|a, b| self.criterion.compare(a, b)),
+            Direction::Highest => choices.sort_by(|a, b| self.criterion.compare(b, a)),
+        }
+
+        // Prevent out-of-bounds
+        let remove_count = self.count.min(choices.len());
+        choices.drain(0..remove_count);
+        choices
+    }
+}
+
+impl RankingPipeline {
+    /// The pipeline given by `Config::min_overlap_factor` and `Config::max_load_factor`: drop the choices with
+    /// the least overlap, then the most loaded
+    pub fn from_factors(min_overlap_factor: usize, max_load_factor: usize) -> Self {
+        Self(vec![
+            RemovalStage {
+                criterion: Criterion::Overlap,
+                direction: Direction::Lowest,
+                count: min_overlap_factor,
+            },
+            RemovalStage {
+                criterion: Criterion::Load,
+                direction: Direction::Highest,
+                count: max_load_factor,
+            },
+        ])
+    }
+
+    /// Runs every stage in order
+    pub fn apply(&self, choices: Vec<Choice>) -> Vec<Choice> {
+        self.0.iter().fold(choices, |choices, stage| stage.apply(choices))
+    }
+
+    /// The total number of choices the pipeline removes
+    pub fn removed(&self) -> usize {
+        self.0.iter().map(|stage| stage.count).sum()
+    }
+}
+
+impl FromStr for Criterion {
+    type Err = BM25Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "load" => Ok(Self::Load),
+            "overlap" => Ok(Self::Overlap),
+            "overlap-ratio" => Ok(Self::OverlapRatio),
+            "hash-order" => Ok(Self::HashOrder),
+            _ => Err(BM25Error::Placement(format!("unknown ranking criterion {s}"))),
+        }
+    }
+}
+
+impl fmt::Display for Criterion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Load => "load",
+            Self::Overlap => "overlap",
+            Self::OverlapRatio => "overlap-ratio",
+            Self::HashOrder => "hash-order",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Direction {
+    type Err = BM25Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "lowest" | "min" => Ok(Self::Lowest),
+            "highest" | "max" => Ok(Self::Highest),
+            _ => Err(BM25Error::Placement(format!("unknown ranking direction {s}"))),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lowest => write!(f, "lowest"),
+            Self::Highest => write!(f, "highest"),
+        }
+    }
+}
+
+impl FromStr for RankingPipeline {
+    type Err = BM25Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let stages = s
+            .split(',')
+            .map(str::trim)
+            .filter(|stage| !stage.is_empty() && *stage != "none")
+            .map(|stage| {
+                let invalid = || BM25Error::Placement(format!("removal stage {stage} should be criterion:direction:count"));
+                let mut parts = stage.split(':');
+                let (Some(criterion), Some(direction), Some(count), None) =
+                    (parts.next(), parts.next(), parts.next(), parts.next())
+                else {
+                    return Err(invalid());
+                };
+                Ok(RemovalStage {
+                    criterion: criterion.parse()?,
+                    direction: direction.parse()?,
+                    count: count.parse().map_err(|_| invalid())?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self(stages))
+    }
+}
+
+impl fmt::Display for RankingPipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stages: Vec<String> = self
+            .0
+            .iter()
+            .filter(|stage| stage.count > 0)
+            .map(|stage| format!("{}:{}:{}", stage.criterion, stage.direction, stage.count))
+            .collect();
+        if stages.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", stages.join(","))
+        }
+    }
+}
+
+impl TryFrom<String> for RankingPipeline {
+    type Error = BM25Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<RankingPipeline> for String {
+    fn from(pipeline: RankingPipeline) -> Self {
+        pipeline.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_by_criterion() {
+        let choices = vec![
+            Choice { choice: 0, bin: 10, load: 5, overlap: 4 },
+            Choice { choice: 1, bin: 11, load: 9, overlap: 1 },
+            Choice { choice: 2, bin: 12, load: 2, overlap: 2 },
+        ];
+        let bins = |pipeline: &str| -> Vec<usize> {
+            let mut bins: Vec<usize> = pipeline
+                .parse::<RankingPipeline>()
+                .unwrap()
+                .apply(choices.clone())
+                .iter()
+                .map(|c| c.bin)
+                .collect();
+            bins.sort_unstable();
+            bins
+        };
+
+        assert_eq!(bins("load:highest:1"), vec![10, 12]);
+        assert_eq!(bins("overlap:lowest:1"), vec![10, 12]);
+        assert_eq!(bins("overlap:highest:1"), vec![11, 12]);
+        assert_eq!(bins("overlap-ratio:highest:1"), vec![10, 11]);
+        assert_eq!(bins("hash-order:highest:2"), vec![10]);
+        assert_eq!(bins("overlap:lowest:1,load:highest:1"), vec![12]);
+        assert_eq!(bins("none"), vec![10, 11, 12]);
+
+        let pipeline = RankingPipeline::from_factors(1, 0);
+        assert_eq!(pipeline.to_string(), "overlap:lowest:1");
+        assert!("load:up:1".parse::<RankingPipeline>().is_err());
+        assert!("load:highest".parse::<RankingPipeline>().is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use crate::bm_calc::{search_alphabet, Searcher};
+use crate::document_ids::DocumentIds;
+use crate::error::{BM25Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use tracing::info;
+
+/// A single ranked search result
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct Posting {
+    /// Internal document ID
+    pub id: u32,
+    /// BM25 score of the document for the keyword
+    pub score: f32,
+}
+
+/// The top results of every keyword in the alphabet, computed once so that `top_k` and `top_k_bins` don't
+/// need to run BM25 again.
+///
+/// Results are ranked by score with ties broken by document ID (see `bm_calc::search_top_k`), so the top k
+/// for any k up to the snapshot's own is a prefix of its list. One snapshot built with the largest k
+/// therefore serves every run, and it can be saved to skip the search stage in later sessions.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PostingSnapshot {
+    /// The number of results stored per keyword
+    k: usize,
+    /// The number of documents in the corpus, which sizes bit-packed IDs in the binary database
+    num_documents: usize,
+    /// Keyword to its ranked results, at most k of them
+    postings: BTreeMap<String, Vec<Posting>>,
+    /// External IDs of the documents the postings refer to, if the corpus has them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    document_ids: Option<DocumentIds>,
+}
+
+impl PostingSnapshot {
+    /// Searches every keyword in the alphabet and keeps its top k results
+    ///
+    /// # Arguments
+    /// * `search_engine` - Search engine to query
+    /// * `alphabet` - The keyword space
+    /// * `k` - Number of results to keep per keyword. Runs with a larger k can't use this snapshot
+    pub fn build<S: Searcher + ?Sized>(search_engine: &S, alphabet: &HashSet<String>, k: usize) -> Self {
+        info!("Searching {} keywords for a top {} snapshot", alphabet.len(), k);
+        let postings = search_alphabet(search_engine, alphabet, k)
+            .into_iter()
+            .map(|(word, results)| {
+                let postings = results
+                    .into_iter()
+                    .map(|result| Posting {
+                        id: result.document.id,
+                        score: result.score,
+                    })
+                    .collect();
+                (word, postings)
+            })
+            .collect();
+        Self {
+            k,
+            num_documents: search_engine.num_documents(),
+            postings,
+            document_ids: None,
+        }
+    }
+
+    /// Attaches the corpus's external document IDs, which are saved with the snapshot and next to the bins of
+    /// every run built from it
+    #[must_use]
+    pub fn with_document_ids(mut self, document_ids: Option<DocumentIds>) -> Self {
+        self.document_ids = document_ids;
+        self
+    }
+
+    /// The external IDs of the documents, if the corpus has them
+    pub const fn document_ids(&self) -> Option<&DocumentIds> {
+        self.document_ids.as_ref()
+    }
+
+    /// The number of results stored per keyword
+    pub const fn k(&self) -> usize {
+        self.k
+    }
+
+    /// The number of documents in the corpus the snapshot was built from
+    pub const fn num_documents(&self) -> usize {
+        self.num_documents
+    }
+
+    /// The number of keywords
+    pub fn len(&self) -> usize {
+        self.postings.len()
+    }
+
+    /// Whether there are no keywords
+    pub fn is_empty(&self) -> bool {
+        self.postings.is_empty()
+    }
+
+    /// The keyword space the snapshot was built over
+    pub fn alphabet(&self) -> HashSet<String> {
+        self.postings.keys().cloned().collect()
+    }
+
+    /// Returns a keyword's top k results, or an empty slice if it isn't in the alphabet
+    ///
+    /// # Errors
+    /// Returns error if k is larger than the snapshot was built with
+    pub fn results(&self, keyword: &str, k: usize) -> Result<&[Posting]> {
+        self.check_k(k)?;
+        Ok(self
+            .postings
+            .get(keyword)
+            .map_or(&[], |postings| &postings[..k.min(postings.len())]))
+    }
+
+    /// Iterates over every keyword with its top k results, sorted by keyword
+    ///
+    /// # Errors
+    /// Returns error if k is larger than the snapshot was built with
+    pub fn top_k(&self, k: usize) -> Result<impl Iterator<Item = (&String, &[Posting])>> {
+        self.check_k(k)?;
+        Ok(self
+            .postings
+            .iter()
+            .map(move |(word, postings)| (word, &postings[..k.min(postings.len())])))
+    }
+
+    /// Makes sure the snapshot holds enough results per keyword for a run with this k
+    fn check_k(&self, k: usize) -> Result<()> {
+        if k > self.k {
+            return Err(BM25Error::Snapshot(format!(
+                "asked for the top {k} results but the snapshot only holds {}",
+                self.k
+            )));
+        }
+        Ok(())
+    }
+
+    /// Writes the snapshot to `filename` as serde JSON
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be written
+    pub fn save(&self, filename: &str) -> Result<()> {
+        let writer = BufWriter::new(File::create(filename)?);
+        serde_json::to_writer(writer, self)?;
+        Ok(())
+    }
+
+    /// Reads a snapshot previously written by `save`
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be read or is not a valid snapshot
+    pub fn load(filename: &str) -> Result<Self> {
+        let reader = BufReader::new(File::open(filename)?);
+        let mut snapshot: Self = serde_json::from_reader(reader)?;
+        snapshot.document_ids = snapshot.document_ids.take().map(DocumentIds::reindex).transpose()?;
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataloader::Text;
+    use crate::bm_calc::{build_search_engine, get_alphabet};
+    use crate::tokenizer::KeywordTokenizer;
+
+    #[test]
+    fn smaller_k_is_a_prefix() {
+        let corpus: Vec<Text> = [
+            "Apples, oranges, papayas, and more papayas.",
+            "Papayas and apples fell from the sky.",
+            "The sky blushed pink as the sun dipped below the horizon.",
+            "An apple a day.",
+        ]
+        .iter()
+        .map(|&s| Text::from(s))
+        .collect();
+        let tokenizer = KeywordTokenizer::default();
+        let alphabet = get_alphabet(&corpus, &tokenizer).unwrap();
+        let search = build_search_engine(&corpus, &tokenizer);
+
+        let large = PostingSnapshot::build(&search, &alphabet, 4);
+        let small = PostingSnapshot::build(&search, &alphabet, 2);
+
+        let from_large: Vec<_> = large.top_k(2).unwrap().collect();
+        let from_small: Vec<_> = small.top_k(2).unwrap().collect();
+        assert_eq!(from_large, from_small);
+        assert!(small.top_k(3).is_err());
+        assert!(small.results("not-a-keyword", 2).unwrap().is_empty());
+        assert_eq!(large.num_documents(), 4);
+
+        let path = std::env::temp_dir().join("bm25_calc_snapshot.json");
+        large.save(path.to_str().unwrap()).unwrap();
+        assert_eq!(PostingSnapshot::load(path.to_str().unwrap()).unwrap(), large);
+
+        let ids = DocumentIds::new(["a", "b", "c", "d"].map(str::to_string).to_vec()).unwrap();
+        let large = large.with_document_ids(Some(ids));
+        large.save(path.to_str().unwrap()).unwrap();
+        let loaded = PostingSnapshot::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(loaded, large);
+        assert_eq!(loaded.document_ids().unwrap().internal("c"), Some(2));
+    }
+}
@@ -0,0 +1,305 @@
+use crate::bm_calc::Searcher;
+use crate::dataloader::{BytesRead, CorpusSource, SourceDocument};
+use crate::error::{BM25Error, Result};
+use crate::tokenizer::{KeywordTokenizer, Keywords};
+use crate::vocabulary::Vocabulary;
+use crate::DocumentIds;
+use bm25::{
+    Document, Embedder, EmbedderBuilder, Embedding, Scorer, SearchResult,
+    TokenEmbedder, TokenEmbedding,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::HashMap;
+use std::mem::size_of;
+use tracing::{debug, info};
+
+/// BM25 k1, the same default the BM25 crate uses
+const K1: f32 = 1.2;
+/// BM25 b, the same default the BM25 crate uses
+const B: f32 = 0.75;
+/// Rough per-document overhead of the scorer's maps, used for the memory estimate
+const DOCUMENT_OVERHEAD: usize = 64;
+/// Rough cost of a `String` and its document frequency in the vocabulary's map on top of its bytes
+const KEYWORD_OVERHEAD: usize = 56;
+
+/// Settings for `StreamingIndex::build`
+#[derive(Copy, Clone, Debug)]
+pub struct StreamingOptions {
+    /// Raw text to read before tokenizing and dropping it, in bytes
+    pub chunk_bytes: usize,
+    /// Stop with an error once the estimated size of the alphabet and index passes this many bytes
+    pub memory_limit: Option<usize>,
+}
+
+impl Default for StreamingOptions {
+    fn default() -> Self {
+        Self {
+            chunk_bytes: 64 * 1024 * 1024,
+            memory_limit: None,
+        }
+    }
+}
+
+/// Term counts for one document, kept until the average document length is known
+struct TermCounts {
+    /// Hashed token and the number of times it appears
+    terms: Vec<(u32, u32)>,
+    /// Number of tokens in the document
+    length: u32,
+}
+
+/// A BM25 index that only holds each document's term weights, not its text.
+///
+/// Built in a single pass over a `CorpusSource`, which also collects the alphabet, so the corpus is only
+/// tokenized once and at most one chunk of raw text is in memory. Scores match `bm_calc::build_search_engine`
+/// over the same documents. Search results have empty contents.
+pub struct StreamingIndex {
+    /// Embeds keyword queries with the corpus' average document length
+    embedder: Embedder<u32, Keywords>,
+    /// Document embeddings and the inverted index
+    scorer: Scorer<u32, u32>,
+    /// The number of documents indexed
+    num_documents: usize,
+}
+
+/// Everything produced by a streaming pass over the corpus
+pub struct StreamedCorpus {
+    /// The search index
+    pub index: StreamingIndex,
+    /// The keywords and their document frequencies, as `bm_calc::get_vocabulary` would return them
+    pub vocabulary: Vocabulary,
+    /// External document IDs, if the source has them
+    pub document_ids: Option<DocumentIds>,
+}
+
+/// Keeps a running estimate of how much memory the pass is holding on to
+struct MemoryBudget {
+    /// Estimated bytes held
+    used: usize,
+    /// Upper limit, if any
+    limit: Option<usize>,
+}
+
+impl MemoryBudget {
+    /// Adds `bytes` to the estimate
+    ///
+    /// # Errors
+    /// Returns error if the estimate goes over the limit
+    const fn charge(&mut self, bytes: usize) -> Result<()> {
+        self.used += bytes;
+        match self.limit {
+            Some(limit) if self.used > limit => Err(BM25Error::MemoryLimit {
+                used: self.used,
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl StreamingIndex {
+    /// Reads the corpus in chunks, building the alphabet and the index in one pass
+    ///
+    /// # Arguments
+    /// * `source` - The corpus to read
+    /// * `options` - Chunk size and memory limit
+    /// * `tokenizer` - Splits documents, with their language, for the alphabet and the index
+    ///
+    /// # Returns
+    /// * `Result<StreamedCorpus>` - The index, the alphabet and the external document IDs
+    ///
+    /// # Errors
+    /// Returns error if the corpus cannot be read, has more than `u32::MAX` documents, or the memory limit is reached
+    pub fn build(source: &CorpusSource, options: StreamingOptions, tokenizer: &KeywordTokenizer) -> Result<StreamedCorpus> {
+        let mut budget = MemoryBudget {
+            used: 0,
+            limit: options.memory_limit,
+        };
+
+        let bar = ProgressBar::new(source.size_on_disk()?);
+        bar.set_style(
+            ProgressStyle::with_template("{bar:40} {bytes}/{total_bytes} ({eta})")
+                .unwrap_or_else(|_| ProgressStyle::default_bar()),
+        );
+
+        let mut vocabulary = Vocabulary::default();
+        let mut documents = Vec::new();
+        let mut ids = Vec::new();
+        let mut total_length: u64 = 0;
+
+        let mut chunk: Vec<SourceDocument> = Vec::new();
+        let mut chunk_bytes = 0;
+        let bytes_read = BytesRead::default();
+        let mut reader = source.documents_counting(&bytes_read)?.peekable();
+
+        while let Some(document) = reader.next() {
+            let document = document?;
+            chunk_bytes += document.0.text.len();
+            chunk.push(document);
+
+            if chunk_bytes < options.chunk_bytes && reader.peek().is_some() {
+                continue;
+            }
+
+            debug!("Indexing a chunk of {} documents ({} bytes)", chunk.len(), chunk_bytes);
+            bar.set_position(bytes_read.get());
+
+            for (text, id) in std::mem::take(&mut chunk) {
+                let tokens = tokenizer.tokenize(&text.text, text.language.as_deref());
+                let mut counts: HashMap<u32, u32> = HashMap::new();
+                for token in &tokens {
+                    *counts.entry(<u32 as TokenEmbedder>::embed(token)).or_default() += 1;
+                }
+
+                let mut terms: Vec<(u32, u32)> = counts.into_iter().collect();
+                terms.sort_unstable();
+                budget.charge(DOCUMENT_OVERHEAD + terms.len() * 2 * size_of::<(u32, u32)>())?;
+
+                for keyword in vocabulary.add_document(&tokens) {
+                    budget.charge(keyword.len() + KEYWORD_OVERHEAD)?;
+                }
+
+                let length = u32::try_from(tokens.len())?;
+                total_length += u64::from(length);
+                documents.push(TermCounts { terms, length });
+                ids.push(id);
+            }
+            chunk_bytes = 0;
+        }
+        bar.finish();
+
+        let num_documents = documents.len();
+        // Same calculation (and fallback) as the BM25 crate when fitting to a corpus
+        let avgdl = if num_documents == 0 {
+            256.0
+        } else {
+            (total_length as f64 / num_documents as f64) as f32
+        };
+
+        info!(
+            "Read {} documents with an average length of {}. Estimated index size is {} bytes",
+            num_documents, avgdl, budget.used
+        );
+
+        let mut scorer = Scorer::new();
+        #[allow(clippy::suboptimal_flops)] // written the same way as the BM25 crate so scores match exactly
+        for (id, document) in documents.into_iter().enumerate() {
+            let length_norm = K1 * (1.0 - B + B * (document.length as f32 / avgdl));
+            let embedding = Embedding(
+                document
+                    .terms
+                    .into_iter()
+                    .map(|(index, count)| {
+                        let count = count as f32;
+                        TokenEmbedding {
+                            index,
+                            value: count * (K1 + 1.0) / (count + length_norm),
+                        }
+                    })
+                    .collect(),
+            );
+            scorer.upsert(&u32::try_from(id)?, embedding);
+        }
+
+        let embedder = EmbedderBuilder::<u32, Keywords>::with_avgdl(avgdl)
+            .tokenizer(Keywords)
+            .k1(K1)
+            .b(B)
+            .build();
+
+        Ok(StreamedCorpus {
+            index: Self {
+                embedder,
+                scorer,
+                num_documents,
+            },
+            vocabulary,
+            document_ids: source.document_ids(ids)?,
+        })
+    }
+
+    /// The number of documents in the index
+    pub const fn len(&self) -> usize {
+        self.num_documents
+    }
+
+    /// Whether the index has no documents
+    pub const fn is_empty(&self) -> bool {
+        self.num_documents == 0
+    }
+}
+
+impl Searcher for StreamingIndex {
+    fn search(&self, query: &str, limit: usize) -> Vec<SearchResult<u32>> {
+        let query_embedding = self.embedder.embed(query);
+        self.scorer
+            .matches(&query_embedding)
+            .into_iter()
+            .take(limit)
+            .map(|scored| SearchResult {
+                document: Document::new(scored.id, ""),
+                score: scored.score,
+            })
+            .collect()
+    }
+
+    fn num_documents(&self) -> usize {
+        self.num_documents
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bm_calc::{build_search_engine, get_vocabulary, search_top_k};
+    use std::fs;
+
+    #[test]
+    fn matches_search_engine() {
+        let corpus = [
+            "Apples, oranges, papayas, and more papayas.",
+            "The sky blushed pink as the sun dipped below the horizon.",
+            "She found a forgotten letter tucked inside an old book.",
+            "Papayas and apples fell from the sky.",
+        ];
+        let path = std::env::temp_dir().join("bm25_calc_streaming.jsonl");
+        let lines: Vec<String> = corpus
+            .iter()
+            .map(|text| serde_json::json!({ "text": text }).to_string())
+            .collect();
+        fs::write(&path, lines.join("\n")).unwrap();
+
+        let source = CorpusSource::new(path.to_str().unwrap(), "text", None).unwrap();
+        let options = StreamingOptions {
+            chunk_bytes: 64,
+            memory_limit: None,
+        };
+        let tokenizer = KeywordTokenizer::default();
+        let streamed = StreamingIndex::build(&source, options, &tokenizer).unwrap();
+
+        let loaded = source.load().unwrap();
+        assert_eq!(streamed.vocabulary, get_vocabulary(&loaded, &tokenizer).unwrap());
+        let search = build_search_engine(&loaded, &tokenizer);
+
+        for word in &streamed.vocabulary.alphabet() {
+            let expected: Vec<(u32, f32)> = search_top_k(&search, word, 3)
+                .into_iter()
+                .map(|r| (r.document.id, r.score))
+                .collect();
+            let actual: Vec<(u32, f32)> = search_top_k(&streamed.index, word, 3)
+                .into_iter()
+                .map(|r| (r.document.id, r.score))
+                .collect();
+            assert_eq!(expected, actual, "results differ for {word}");
+        }
+
+        let limited = StreamingOptions {
+            chunk_bytes: 64,
+            memory_limit: Some(100),
+        };
+        assert!(matches!(
+            StreamingIndex::build(&source, limited, &tokenizer),
+            Err(BM25Error::MemoryLimit { .. })
+        ));
+    }
+}
@@ -0,0 +1,293 @@
+use crate::bm_calc::{self, Metadata};
+use crate::error::{BM25Error, Result};
+use crate::placement::PlacementKind;
+use crate::snapshot::PostingSnapshot;
+use crate::Config;
+use std::fs::File;
+use std::io::{BufWriter, Write as _};
+use std::str::FromStr;
+use tracing::{debug, info, warn};
+
+/// A list of values to sweep a single `Config` field over.
+///
+/// Parsed from a comma separated list where each entry is either a single value (`4`),
+/// an inclusive range (`1..4`) or an inclusive range with a step (`256..1024:256`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParamRange(pub Vec<usize>);
+
+impl FromStr for ParamRange {
+    type Err = BM25Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || BM25Error::InvalidRange(s.to_string());
+        let mut values = Vec::new();
+
+        for part in s.split(',').map(str::trim) {
+            let (range, step) = match part.split_once(':') {
+                Some((range, step)) => (range, Some(step.parse::<usize>().map_err(|_| invalid())?)),
+                None => (part, None),
+            };
+
+            if let Some((start, end)) = range.split_once("..") {
+                let step = step.unwrap_or(1);
+                let start: usize = start.parse().map_err(|_| invalid())?;
+                let end: usize = end.parse().map_err(|_| invalid())?;
+                if step == 0 || start > end {
+                    return Err(invalid());
+                }
+                values.extend((start..=end).step_by(step));
+            } else if step.is_some() {
+                // A step only means something for a range
+                return Err(invalid());
+            } else {
+                values.push(range.parse().map_err(|_| invalid())?);
+            }
+        }
+
+        values.sort_unstable();
+        values.dedup();
+        Ok(Self(values))
+    }
+}
+
+/// The values to try for every `Config` field. The sweep runs the full cartesian product.
+#[derive(Clone, Debug)]
+pub struct SweepGrid {
+    /// Values of k
+    pub k: Vec<usize>,
+    /// Values of d
+    pub d: Vec<usize>,
+    /// Values of max_bins
+    pub max_bins: Vec<usize>,
+    /// Values of filter_k
+    pub filter_k: Vec<usize>,
+    /// Values of max_load_factor
+    pub max_load_factor: Vec<usize>,
+    /// Values of min_overlap_factor
+    pub min_overlap_factor: Vec<usize>,
+}
+
+/// A single point in the sweep
+pub struct SweepResult {
+    /// The config that was run
+    pub config: Config,
+    /// The metadata `top_k_bins` returned for it
+    pub metadata: Metadata,
+    /// Whether no other point has both fewer total items and a smaller max load
+    pub pareto: bool,
+}
+
+impl SweepGrid {
+    /// Creates a grid that only contains the values in `base`. Fields can then be overwritten with ranges.
+    pub fn from_base(base: &Config) -> Self {
+        Self {
+            k: vec![base.k],
+            d: vec![base.d],
+            max_bins: vec![base.max_bins],
+            filter_k: vec![base.filter_k],
+            max_load_factor: vec![base.max_load_factor],
+            min_overlap_factor: vec![base.min_overlap_factor],
+        }
+    }
+
+    /// Expands the grid into every combination of values. Combinations that have no bins, or whose d-choice
+    /// removal would remove all d choices, are skipped. When `base.removal` is set it replaces `max_load_factor` and
+    /// `min_overlap_factor`, so those aren't swept and only the base values are used.
+    ///
+    /// # Arguments
+    /// * `base` - Config used for the fields that are not swept (e.g. `save_result`)
+    pub fn configs(&self, base: &Config) -> Vec<Config> {
+        let (max_load_factors, min_overlap_factors) = base.removal.as_ref().map_or_else(
+            || (self.max_load_factor.clone(), self.min_overlap_factor.clone()),
+            |removal| {
+                if self.max_load_factor.len() > 1 || self.min_overlap_factor.len() > 1 {
+                    warn!("Not sweeping max_load_factor or min_overlap_factor, as the removal pipeline {removal} replaces them");
+                }
+                (vec![base.max_load_factor], vec![base.min_overlap_factor])
+            },
+        );
+
+        let mut configs = Vec::new();
+        for &k in &self.k {
+            for &d in &self.d {
+                for &max_bins in &self.max_bins {
+                    for &filter_k in &self.filter_k {
+                        for &max_load_factor in &max_load_factors {
+                            for &min_overlap_factor in &min_overlap_factors {
+                                let config = Config {
+                                    k,
+                                    d,
+                                    max_bins,
+                                    filter_k,
+                                    max_load_factor,
+                                    min_overlap_factor,
+                                    ..base.clone()
+                                };
+                                let removal = config.removal_pipeline();
+                                let no_choices =
+                                    config.placement == PlacementKind::DChoice && removal.removed() >= d;
+                                if max_bins == 0 || no_choices {
+                                    debug!("Skipping sweep point d={d}, max_bins={max_bins}, removing {removal}");
+                                    continue;
+                                }
+                                configs.push(config);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        configs
+    }
+}
+
+/// Runs `top_k_bins` for every combination in the grid, reusing the same search results
+///
+/// # Arguments
+/// * `snapshot` - Search results for every keyword, built with at least the largest k in the grid
+/// * `grid` - The values to sweep over
+/// * `base` - Config used for the fields that are not swept
+///
+/// # Returns
+/// * `Result<Vec<SweepResult>>` - One result per combination, with the Pareto frontier marked
+///
+/// # Errors
+/// Returns error if any of the runs fail
+pub fn run_sweep(
+    snapshot: &PostingSnapshot,
+    grid: &SweepGrid,
+    base: &Config,
+) -> Result<Vec<SweepResult>> {
+    let configs = grid.configs(base);
+    info!("Sweeping over {} configurations", configs.len());
+
+    let mut results = Vec::with_capacity(configs.len());
+    for config in configs {
+        let (metadata, _) = bm_calc::top_k_bins(snapshot, config.clone())?;
+        results.push(SweepResult {
+            config,
+            metadata,
+            pareto: false,
+        });
+    }
+
+    mark_pareto_frontier(&mut results);
+    Ok(results)
+}
+
+/// Marks every result that is not dominated on (total items, max load), both of which we want to minimise
+fn mark_pareto_frontier(results: &mut [SweepResult]) {
+    let points: Vec<(usize, usize)> = results
+        .iter()
+        .map(|r| (r.metadata.total_items, r.metadata.max_load))
+        .collect();
+
+    for (result, &(total, load)) in results.iter_mut().zip(points.iter()) {
+        result.pareto = !points
+            .iter()
+            .any(|&(t, l)| t <= total && l <= load && (t < total || l < load));
+    }
+}
+
+/// Short description of a config, used as the row name in the sweep summary
+pub fn describe_config(config: &Config) -> String {
+    format!(
+        "k={} d={} bins={} filter_k={} removal={} placement={} order={} hash={}",
+        config.k,
+        config.d,
+        config.max_bins,
+        config.filter_k,
+        config.removal_pipeline(),
+        config.placement,
+        config.order,
+        config.hash
+    )
+}
+
+/// Writes one CSV row per sweep point
+///
+/// # Arguments
+/// * `results` - Output of `run_sweep`
+/// * `filename` - Where to write the CSV
+///
+/// # Errors
+/// Returns error if the file cannot be written
+pub fn write_csv(results: &[SweepResult], filename: &str) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    writeln!(
+        writer,
+        "k,d,max_bins,filter_k,max_load_factor,min_overlap_factor,removal,num_bins,removed_items,total_items,average_load_per_bin,max_load,keywords_with_overlap,stash_size,evictions,capacity,padding_overhead,real_fraction,pareto"
+    )?;
+
+    for SweepResult { config, metadata, pareto } in results {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},\"{}\",{},{},{},{},{},{},{},{},{},{},{},{}",
+            config.k,
+            config.d,
+            config.max_bins,
+            config.filter_k,
+            config.max_load_factor,
+            config.min_overlap_factor,
+            metadata.removal,
+            metadata.num_bins,
+            metadata.removed_items,
+            metadata.total_items,
+            metadata.average_load_per_bin,
+            metadata.max_load,
+            metadata.keywords_with_overlap,
+            metadata.stash_size,
+            metadata.evictions,
+            metadata.capacity,
+            metadata.padding_overhead,
+            metadata.real_fraction,
+            pareto
+        )?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!("4".parse::<ParamRange>().unwrap().0, vec![4]);
+        assert_eq!("1..3".parse::<ParamRange>().unwrap().0, vec![1, 2, 3]);
+        assert_eq!("256..1024:256,10".parse::<ParamRange>().unwrap().0, vec![10, 256, 512, 768, 1024]);
+        assert!("4..1".parse::<ParamRange>().is_err());
+        assert!("1..4:0".parse::<ParamRange>().is_err());
+        assert!("a".parse::<ParamRange>().is_err());
+        assert!("4:2".parse::<ParamRange>().is_err());
+    }
+
+    #[test]
+    fn removal_replaces_factor_ranges() {
+        let base = Config {
+            d: 4,
+            removal: Some("load:highest:1".parse().unwrap()),
+            ..Config::default()
+        };
+        let mut grid = SweepGrid::from_base(&base);
+        grid.max_load_factor = vec![0, 1, 2];
+        grid.min_overlap_factor = vec![0, 1];
+        let configs = grid.configs(&base);
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs[0].max_load_factor, base.max_load_factor);
+
+        let base = Config { removal: None, ..base };
+        assert_eq!(grid.configs(&base).len(), 6);
+
+        // Only d-choice placement drops choices, so the others keep every point
+        grid.d = vec![1];
+        assert_eq!(grid.configs(&base).len(), 1);
+        let base = Config {
+            placement: PlacementKind::LeastLoaded,
+            ..base
+        };
+        assert_eq!(grid.configs(&base).len(), 6);
+    }
+}
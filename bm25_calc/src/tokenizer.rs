@@ -0,0 +1,536 @@
+use crate::error::{BM25Error, Result};
+use bm25::{DefaultTokenizer, Language, Tokenizer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::sync::Arc;
+use whatlang::{Detector, Lang};
+
+/// The keyword numbers are replaced with by `NumericTokens::Placeholder`
+pub const NUMBER_TOKEN: &str = "#";
+
+/// Joins the words of a phrase keyword, e.g. `new+york`. Word splitting never leaves it inside a token
+pub const PHRASE_SEPARATOR: char = '+';
+
+/// Every language the BM25 crate's tokenizer supports, with its name, ISO 639-1 code and `whatlang` language
+const LANGUAGES: [(Language, &str, &str, Lang); 17] = [
+    (Language::Arabic, "arabic", "ar", Lang::Ara),
+    (Language::Danish, "danish", "da", Lang::Dan),
+    (Language::Dutch, "dutch", "nl", Lang::Nld),
+    (Language::English, "english", "en", Lang::Eng),
+    (Language::French, "french", "fr", Lang::Fra),
+    (Language::German, "german", "de", Lang::Deu),
+    (Language::Greek, "greek", "el", Lang::Ell),
+    (Language::Hungarian, "hungarian", "hu", Lang::Hun),
+    (Language::Italian, "italian", "it", Lang::Ita),
+    (Language::Norwegian, "norwegian", "no", Lang::Nob),
+    (Language::Portuguese, "portuguese", "pt", Lang::Por),
+    (Language::Romanian, "romanian", "ro", Lang::Ron),
+    (Language::Russian, "russian", "ru", Lang::Rus),
+    (Language::Spanish, "spanish", "es", Lang::Spa),
+    (Language::Swedish, "swedish", "sv", Lang::Swe),
+    (Language::Tamil, "tamil", "ta", Lang::Tam),
+    (Language::Turkish, "turkish", "tr", Lang::Tur),
+];
+
+/// Parses a language from its English name or ISO 639-1 code, e.g. `german` or `de`
+///
+/// # Errors
+/// Returns error if the BM25 crate doesn't support the language
+pub fn parse_language(s: &str) -> Result<Language> {
+    let s = s.to_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|(_, name, code, _)| *name == s || *code == s)
+        .map(|(language, _, _, _)| language.clone())
+        .ok_or_else(|| BM25Error::Tokenizer(format!("unsupported language {s}")))
+}
+
+/// The lowercase English name of a language, as `parse_language` reads it
+pub fn language_name(language: &Language) -> &'static str {
+    LANGUAGES
+        .iter()
+        .find(|(candidate, _, _, _)| candidate == language)
+        .map_or("unknown", |(_, name, _, _)| name)
+}
+
+/// Whether a token is a keyword tagged with a supported language's code, like `de:haus`
+fn is_tagged(token: &str) -> bool {
+    token
+        .split_once(':')
+        .is_some_and(|(code, word)| !word.is_empty() && LANGUAGES.iter().any(|(_, _, known, _)| *known == code))
+}
+
+/// The number of words in a keyword: 1 for a word, 2 for a bigram like `new+york` and so on
+pub fn ngram_order(keyword: &str) -> usize {
+    keyword.matches(PHRASE_SEPARATOR).count() + 1
+}
+
+/// The words a phrase keyword is made of, each tagged with the phrase's language if it has one, so
+/// `en:new+york` gives `en:new` and `en:york`. A single word gives itself
+pub fn phrase_words(keyword: &str) -> Vec<String> {
+    let (tag, phrase) = match keyword.split_once(':') {
+        Some((code, phrase)) if is_tagged(keyword) => (Some(code), phrase),
+        _ => (None, keyword),
+    };
+    phrase
+        .split(PHRASE_SEPARATOR)
+        .map(|word| tag.map_or_else(|| word.to_string(), |code| format!("{code}:{word}")))
+        .collect()
+}
+
+/// Counts keywords by their number of words. Index 0 holds the single words, 1 the bigrams and so on
+pub fn ngram_counts<'a>(keywords: impl IntoIterator<Item = &'a str>) -> Vec<usize> {
+    let mut counts = vec![0; 1];
+    for keyword in keywords {
+        let order = ngram_order(keyword);
+        if counts.len() < order {
+            counts.resize(order, 0);
+        }
+        counts[order - 1] += 1;
+    }
+    counts
+}
+
+/// Serialises a `Language` as its name, so experiment files can say `"language": "german"`
+mod language_serde {
+    use bm25::Language;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    /// Writes the language's name
+    pub fn serialize<S: Serializer>(language: &Language, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(super::language_name(language))
+    }
+
+    /// Reads a language name or code
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Language, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        super::parse_language(&name).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Which words are dropped before stemming
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stopwords {
+    /// Keep every word
+    None,
+    /// The BM25 crate's list for the configured language
+    #[default]
+    BuiltIn,
+    /// One word per line from a file. The words are normalized and stemmed like the text, so they match its tokens
+    File(String),
+}
+
+impl FromStr for Stopwords {
+    type Err = BM25Error;
+
+    /// `none`, `built-in`, or otherwise the path of a stopword file
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "none" => Ok(Self::None),
+            "built-in" | "builtin" => Ok(Self::BuiltIn),
+            "" => Err(BM25Error::Tokenizer("empty stopword file name".to_string())),
+            _ => Ok(Self::File(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Stopwords {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::BuiltIn => write!(f, "built-in"),
+            Self::File(path) => write!(f, "{path}"),
+        }
+    }
+}
+
+/// What happens to tokens that are numbers, such as `1999` or `3.14`
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NumericTokens {
+    /// Numbers are keywords like any other word
+    #[default]
+    Keep,
+    /// Numbers are dropped
+    Drop,
+    /// Every number becomes the single keyword `NUMBER_TOKEN`
+    Placeholder,
+}
+
+impl FromStr for NumericTokens {
+    type Err = BM25Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "keep" => Ok(Self::Keep),
+            "drop" => Ok(Self::Drop),
+            "placeholder" => Ok(Self::Placeholder),
+            _ => Err(BM25Error::Tokenizer(format!("unknown numeric token handling {s}"))),
+        }
+    }
+}
+
+impl fmt::Display for NumericTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Keep => write!(f, "keep"),
+            Self::Drop => write!(f, "drop"),
+            Self::Placeholder => write!(f, "placeholder"),
+        }
+    }
+}
+
+/// How documents and queries are split into keywords.
+///
+/// The default is the tokenizer the alphabet has always used: English, normalized, with built-in stopwords and
+/// stemming, keeping every token.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct TokenizerConfig {
+    /// Language for stemming and the built-in stopwords. In multilingual mode, the language of documents whose
+    /// language can't be detected
+    #[serde(with = "language_serde")]
+    pub language: Language,
+    /// Tokenize each document with its own language's stemmer and stopwords, and tag keywords with the language's
+    /// code (`de:haus`, `en:house`) so the same word in two languages is hashed independently. The language is the
+    /// one the document gives, if any, or is detected offline with a trigram model
+    pub multilingual: bool,
+    /// Whether tokens are stemmed
+    pub stemming: bool,
+    /// Which stopwords are removed
+    pub stopwords: Stopwords,
+    /// Whether text is folded to ASCII (e.g. `café` to `cafe`) before splitting
+    pub normalization: bool,
+    /// Shortest token kept, in characters
+    pub min_token_len: usize,
+    /// Longest token kept, in characters
+    pub max_token_len: Option<usize>,
+    /// What happens to numbers
+    pub numbers: NumericTokens,
+    /// Longest phrase emitted as a keyword, in words. 1 keeps single words only, 2 adds a bigram such as
+    /// `new+york` for every pair of adjacent tokens, 3 adds trigrams too. Phrases are formed after stopwords and
+    /// short or long tokens are dropped
+    pub ngrams: usize,
+}
+
+impl Default for TokenizerConfig {
+    fn default() -> Self {
+        Self {
+            language: Language::English,
+            multilingual: false,
+            stemming: true,
+            stopwords: Stopwords::BuiltIn,
+            normalization: true,
+            min_token_len: 1,
+            max_token_len: None,
+            numbers: NumericTokens::Keep,
+            ngrams: 1,
+        }
+    }
+}
+
+/// Tokenizes text in one language
+struct Analyzer {
+    /// ISO 639-1 code of the language, used to tag keywords in multilingual mode
+    code: &'static str,
+    /// Normalizes, splits, stems and removes built-in stopwords
+    base: DefaultTokenizer,
+    /// Stemmed words from a stopword file
+    stopwords: HashSet<String>,
+}
+
+/// The parts of a `KeywordTokenizer`, shared between its clones
+struct TokenizerParts {
+    /// The settings it was built from
+    config: TokenizerConfig,
+    /// One analyzer per language in multilingual mode, or just the configured language's
+    analyzers: Vec<Analyzer>,
+    /// Index of the configured language's analyzer
+    fallback: usize,
+    /// Detects a document's language, among those the analyzers cover
+    detector: Detector,
+}
+
+/// The tokenizer built from a `TokenizerConfig`.
+///
+/// Clones share one instance, so the alphabet, the search engine (in memory or streaming) and query evaluation all
+/// split text the same way. The search engines are given the keywords this emits rather than raw text, see
+/// `Keywords`.
+#[derive(Clone)]
+pub struct KeywordTokenizer {
+    /// Shared state
+    parts: Arc<TokenizerParts>,
+}
+
+impl KeywordTokenizer {
+    /// Builds the tokenizer, reading the stopword file if there is one
+    ///
+    /// # Errors
+    /// Returns error if the stopword file can't be read, the token lengths are inconsistent or `ngrams` is 0
+    pub fn new(config: &TokenizerConfig) -> Result<Self> {
+        if config.ngrams == 0 {
+            return Err(BM25Error::Tokenizer("ngrams must be at least 1".to_string()));
+        }
+        if config.max_token_len.is_some_and(|max| max < config.min_token_len) {
+            return Err(BM25Error::Tokenizer(format!(
+                "max_token_len {:?} is below min_token_len {}",
+                config.max_token_len, config.min_token_len
+            )));
+        }
+
+        let stopword_file = match &config.stopwords {
+            Stopwords::File(path) => Some(fs::read_to_string(path)?),
+            Stopwords::None | Stopwords::BuiltIn => None,
+        };
+
+        let languages: Vec<&(Language, &str, &str, Lang)> = LANGUAGES
+            .iter()
+            .filter(|(language, _, _, _)| config.multilingual || *language == config.language)
+            .collect();
+        let analyzers = languages
+            .iter()
+            .map(|(language, _, code, _)| {
+                let base = DefaultTokenizer::builder()
+                    .language_mode(language.clone())
+                    .normalization(config.normalization)
+                    .stopwords(config.stopwords == Stopwords::BuiltIn)
+                    .stemming(config.stemming)
+                    .build();
+                // Stem the file's words with each language, so they match that language's tokens
+                let stopwords = stopword_file
+                    .iter()
+                    .flat_map(|file| file.lines())
+                    .flat_map(|line| base.tokenize(line))
+                    .collect();
+                Analyzer { code, base, stopwords }
+            })
+            .collect();
+        let fallback = languages
+            .iter()
+            .position(|(language, _, _, _)| *language == config.language)
+            .unwrap_or_default();
+        let detector = Detector::with_allowlist(languages.iter().map(|(_, _, _, lang)| *lang).collect());
+
+        Ok(Self {
+            parts: Arc::new(TokenizerParts {
+                config: config.clone(),
+                analyzers,
+                fallback,
+                detector,
+            }),
+        })
+    }
+
+    /// The settings the tokenizer was built from
+    pub fn config(&self) -> &TokenizerConfig {
+        &self.parts.config
+    }
+
+    /// The language a text is in, as an index into the analyzers. A given language the tokenizer doesn't support
+    /// is detected instead, and the configured language is used when detection isn't confident, as for most
+    /// short texts
+    fn language(&self, text: &str, language: Option<&str>) -> usize {
+        let parts = &self.parts;
+        let given = language
+            .and_then(|language| parse_language(language).ok())
+            .map(|language| language_code(&language));
+        let code = given.or_else(|| {
+            let info = parts.detector.detect(text).filter(whatlang::Info::is_reliable)?;
+            let lang = info.lang();
+            LANGUAGES
+                .iter()
+                .find(|(_, _, _, candidate)| *candidate == lang)
+                .map(|(_, _, code, _)| *code)
+        });
+        code.and_then(|code| parts.analyzers.iter().position(|analyzer| analyzer.code == code))
+            .unwrap_or(parts.fallback)
+    }
+
+    /// Splits a document or query into keywords
+    ///
+    /// # Arguments
+    /// * `input_text` - The text
+    /// * `language` - The language the text is in, as `parse_language` reads it, if known. Only used in
+    ///   multilingual mode, where texts without one have their language detected
+    pub fn tokenize(&self, input_text: &str, language: Option<&str>) -> Vec<String> {
+        let parts = &self.parts;
+        let config = &parts.config;
+
+        let index = if config.multilingual {
+            self.language(input_text, language)
+        } else {
+            parts.fallback
+        };
+        let analyzer = &parts.analyzers[index];
+
+        let words: Vec<String> = analyzer
+            .base
+            .tokenize(input_text)
+            .into_iter()
+            .filter(|token| !analyzer.stopwords.contains(token))
+            .filter(|token| {
+                let length = token.chars().count();
+                length >= config.min_token_len && config.max_token_len.is_none_or(|max| length <= max)
+            })
+            .filter_map(|token| match config.numbers {
+                NumericTokens::Keep => Some(token),
+                _ if !is_number(&token) => Some(token),
+                NumericTokens::Drop => None,
+                NumericTokens::Placeholder => Some(NUMBER_TOKEN.to_string()),
+            })
+            .collect();
+
+        let separator = PHRASE_SEPARATOR.to_string();
+        let phrases: Vec<String> = (2..=config.ngrams)
+            .flat_map(|n| words.windows(n).map(|window| window.join(&separator)))
+            .collect();
+
+        words
+            .into_iter()
+            .chain(phrases)
+            .map(|token| {
+                if config.multilingual {
+                    format!("{}:{token}", analyzer.code)
+                } else {
+                    token
+                }
+            })
+            .collect()
+    }
+}
+
+/// The ISO 639-1 code of a language
+fn language_code(language: &Language) -> &'static str {
+    LANGUAGES
+        .iter()
+        .find(|(candidate, _, _, _)| candidate == language)
+        .map_or("", |(_, _, code, _)| code)
+}
+
+impl Default for KeywordTokenizer {
+    fn default() -> Self {
+        Self::new(&TokenizerConfig::default()).expect("The default tokenizer reads no files")
+    }
+}
+
+impl fmt::Debug for KeywordTokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "KeywordTokenizer({:?})", self.parts.config)
+    }
+}
+
+/// Splits text that is already keywords, separated by whitespace, as `KeywordTokenizer` emits them.
+///
+/// The search engines index documents tokenized up front with their language, and are searched with alphabet
+/// keywords or tokenized queries, so the BM25 crate's embedder only has to split them back apart. Keywords never
+/// contain whitespace, so an alphabet keyword is always searched exactly as it is.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Keywords;
+
+impl Tokenizer for Keywords {
+    fn tokenize(&self, input_text: &str) -> Vec<String> {
+        input_text.split_whitespace().map(str::to_string).collect()
+    }
+}
+
+/// Whether a token is a number: digits, possibly with `.` or `,` separators
+fn is_number(token: &str) -> bool {
+    token.chars().any(|c| c.is_numeric()) && token.chars().all(|c| c.is_numeric() || c == '.' || c == ',')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_tokenizer() {
+        let text = "The 3 quick foxes jumped over 1,000 lazy dogs in 1999";
+        assert_eq!(
+            KeywordTokenizer::default().tokenize(text, None),
+            ["3", "quick", "fox", "jump", "1,000", "lazi", "dog", "1999"]
+        );
+
+        let path = std::env::temp_dir().join("bm25_calc_stopwords.txt");
+        fs::write(&path, "Jumping\ndogs\n").unwrap();
+        let config = TokenizerConfig {
+            stopwords: Stopwords::File(path.to_str().unwrap().to_string()),
+            min_token_len: 4,
+            max_token_len: Some(5),
+            numbers: NumericTokens::Placeholder,
+            ..TokenizerConfig::default()
+        };
+        // "over" is only a built-in stopword, and the file's words are stemmed to match
+        assert_eq!(
+            KeywordTokenizer::new(&config).unwrap().tokenize(text, None),
+            ["quick", "over", NUMBER_TOKEN, "lazi", NUMBER_TOKEN]
+        );
+
+        let unstemmed = TokenizerConfig {
+            language: parse_language("de").unwrap(),
+            stemming: false,
+            numbers: NumericTokens::Drop,
+            ..TokenizerConfig::default()
+        };
+        assert_eq!(
+            KeywordTokenizer::new(&unstemmed).unwrap().tokenize("Die Häuser sind 200 Jahre alt", None),
+            ["hauser", "jahre", "alt"]
+        );
+
+        assert!(parse_language("klingon").is_err());
+        assert_eq!(language_name(&parse_language("German").unwrap()), "german");
+        assert_eq!("none".parse::<Stopwords>().unwrap(), Stopwords::None);
+        assert!("sometimes".parse::<NumericTokens>().is_err());
+
+        let phrases = KeywordTokenizer::new(&TokenizerConfig {
+            ngrams: 3,
+            ..TokenizerConfig::default()
+        })
+        .unwrap();
+        // Stopwords are dropped before adjacent words are joined
+        assert_eq!(
+            phrases.tokenize("The Bank of New York", None),
+            ["bank", "new", "york", "bank+new", "new+york", "bank+new+york"]
+        );
+        // Text that looks like a phrase keyword is still split into words. Keywords are only searched as they are
+        assert_eq!(phrases.tokenize("new+york", None), ["new", "york", "new+york"]);
+        assert_eq!(phrases.tokenize("c+c", None), ["c", "c", "c+c"]);
+        assert_eq!(Keywords.tokenize("new+york"), ["new+york"]);
+        assert_eq!(ngram_counts(phrases.tokenize("The Bank of New York", None).iter().map(String::as_str)), [3, 2, 1]);
+        assert!(KeywordTokenizer::new(&TokenizerConfig { ngrams: 0, ..TokenizerConfig::default() }).is_err());
+    }
+
+    #[test]
+    fn multilingual_keywords() {
+        let tokenizer = KeywordTokenizer::new(&TokenizerConfig {
+            multilingual: true,
+            ..TokenizerConfig::default()
+        })
+        .unwrap();
+
+        // The same word is a different keyword in each language
+        assert_eq!(tokenizer.tokenize("Das Haus", Some("de")), ["de:haus"]);
+        assert_eq!(tokenizer.tokenize("the house", Some("English")), ["en:hous"]);
+
+        // Documents without a language, and ones with an unsupported language, are detected
+        assert_eq!(
+            tokenizer.tokenize("Die Häuser in der Altstadt sind sehr alt und wunderschön", None),
+            ["de:haus", "de:altstadt", "de:alt", "de:wunderschon"]
+        );
+        assert_eq!(
+            tokenizer.tokenize("Les maisons de la vieille ville sont très anciennes", Some("klingon")),
+            ["fr:maison", "fr:vieil", "fr:vill", "fr:tre", "fr:ancien"]
+        );
+
+        // Too short to detect reliably, so the configured language is used
+        assert_eq!(tokenizer.tokenize("old house garden", None), ["en:old", "en:hous", "en:garden"]);
+        // Text that looks like tagged keywords is still text. Keywords from the alphabet are searched as they are
+        assert_eq!(tokenizer.tokenize("de:haus en:hous", None), ["de:haus", "en:hous"].map(|word| format!("en:{word}")));
+        assert_eq!(Keywords.tokenize("de:haus  en:hous"), ["de:haus", "en:hous"]);
+        assert_eq!(phrase_words("de:alt+haus"), ["de:alt", "de:haus"]);
+        assert_eq!(phrase_words("haus"), ["haus"]);
+    }
+}
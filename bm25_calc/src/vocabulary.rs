@@ -0,0 +1,330 @@
+use crate::error::{BM25Error, Result};
+use crate::tokenizer::phrase_words;
+use regex::RegexSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use tracing::info;
+
+/// Every keyword the tokenizer produced, with the number of documents it appears in
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Vocabulary {
+    /// Keyword to the number of documents containing it
+    document_frequency: HashMap<String, usize>,
+    /// The number of documents counted
+    num_documents: usize,
+}
+
+impl Vocabulary {
+    /// Counts a document's keywords
+    ///
+    /// # Arguments
+    /// * `tokens` - The document's tokens, repeats included
+    ///
+    /// # Returns
+    /// * `Vec<&str>` - The keywords seen for the first time, so callers can account for their memory
+    pub fn add_document<'a>(&mut self, tokens: &'a [String]) -> Vec<&'a str> {
+        self.num_documents += 1;
+        let mut new = Vec::new();
+        let distinct: HashSet<&str> = tokens.iter().map(String::as_str).collect();
+        for token in distinct {
+            match self.document_frequency.get_mut(token) {
+                Some(frequency) => *frequency += 1,
+                None => {
+                    self.document_frequency.insert(token.to_string(), 1);
+                    new.push(token);
+                }
+            }
+        }
+        new
+    }
+
+    /// The number of documents counted
+    pub const fn num_documents(&self) -> usize {
+        self.num_documents
+    }
+
+    /// The number of keywords
+    pub fn len(&self) -> usize {
+        self.document_frequency.len()
+    }
+
+    /// Whether there are no keywords
+    pub fn is_empty(&self) -> bool {
+        self.document_frequency.is_empty()
+    }
+
+    /// The number of documents a keyword appears in, 0 if it never does
+    pub fn document_frequency(&self, keyword: &str) -> usize {
+        self.document_frequency.get(keyword).copied().unwrap_or_default()
+    }
+
+    /// Every keyword, unpruned
+    pub fn alphabet(&self) -> HashSet<String> {
+        self.document_frequency.keys().cloned().collect()
+    }
+
+    /// How much more often a phrase's words share a document than they would by chance, as the pointwise mutual
+    /// information `ln(P(phrase) / (P(word 1) * ... * P(word n)))` over document frequencies. 0 for a single word
+    pub fn pmi(&self, keyword: &str) -> f64 {
+        let words = phrase_words(keyword);
+        if words.len() < 2 {
+            return 0.0;
+        }
+        let documents = self.num_documents.max(1) as f64;
+        let probability = |keyword: &str| self.document_frequency(keyword).max(1) as f64 / documents;
+        words.iter().fold(probability(keyword).ln(), |pmi, word| pmi - probability(word).ln())
+    }
+
+    /// Applies the filters in order, deny-list first, and returns the keywords that are left
+    ///
+    /// # Arguments
+    /// * `filter` - The filters
+    ///
+    /// # Returns
+    /// * `(HashSet<String>, PruningReport)` - The pruned alphabet and how many keywords each filter removed
+    ///
+    /// # Errors
+    /// Returns error if a deny-list pattern isn't a valid regex
+    pub fn prune(&self, filter: &VocabularyFilter) -> Result<(HashSet<String>, PruningReport)> {
+        let deny = RegexSet::new(&filter.deny)
+            .map_err(|error| BM25Error::Vocabulary(format!("invalid deny-list pattern: {error}")))?;
+        let min_df = filter.min_df.map_or(0.0, |min| min.documents(self.num_documents));
+        let max_df = filter.max_df.map_or(f64::INFINITY, |max| max.documents(self.num_documents));
+        let min_phrase_df = filter.min_phrase_df.map_or(0.0, |min| min.documents(self.num_documents));
+        let min_pmi = filter.min_pmi.unwrap_or(f64::NEG_INFINITY);
+        let is_weak_phrase = |keyword: &str, frequency: usize| {
+            phrase_words(keyword).len() > 1 && ((frequency as f64) < min_phrase_df || self.pmi(keyword) < min_pmi)
+        };
+
+        let mut report = PruningReport {
+            keywords: self.len(),
+            ..PruningReport::default()
+        };
+        let mut kept: Vec<(&str, usize)> = Vec::with_capacity(self.len());
+        for (keyword, &frequency) in &self.document_frequency {
+            if deny.is_match(keyword) {
+                report.denied += 1;
+            } else if (frequency as f64) < min_df {
+                report.below_min_df += 1;
+            } else if frequency as f64 > max_df {
+                report.above_max_df += 1;
+            } else if is_weak_phrase(keyword, frequency) {
+                report.weak_phrases += 1;
+            } else {
+                kept.push((keyword, frequency));
+            }
+        }
+
+        // Most frequent first, ties broken by keyword so pruning is deterministic
+        kept.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        report.most_frequent = filter.drop_top_n.unwrap_or_default().min(kept.len());
+        let kept = &kept[report.most_frequent..];
+        let capped = filter.max_keywords.map_or(kept, |max| &kept[..max.min(kept.len())]);
+        report.over_cap = kept.len() - capped.len();
+        report.kept = capped.len();
+
+        info!(
+            "Pruned the alphabet from {} to {} keywords: {} denied, {} below the minimum document frequency, {} above \
+             the maximum, {} weak phrases, {} among the most frequent and {} over the cap",
+            report.keywords,
+            report.kept,
+            report.denied,
+            report.below_min_df,
+            report.above_max_df,
+            report.weak_phrases,
+            report.most_frequent,
+            report.over_cap
+        );
+
+        Ok((capped.iter().map(|(keyword, _)| keyword.to_string()).collect(), report))
+    }
+}
+
+/// A document frequency bound, as a number of documents or a fraction of the corpus
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Frequency {
+    /// A number of documents
+    Count(usize),
+    /// A fraction of the documents, from 0 to 1
+    Fraction(f64),
+}
+
+impl Frequency {
+    /// The bound as a number of documents
+    fn documents(self, num_documents: usize) -> f64 {
+        match self {
+            Self::Count(count) => count as f64,
+            Self::Fraction(fraction) => fraction * num_documents as f64,
+        }
+    }
+}
+
+impl FromStr for Frequency {
+    type Err = BM25Error;
+
+    /// Whole numbers are counts, while `0.05` and `5%` are fractions of the corpus
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || BM25Error::Vocabulary(format!("invalid document frequency {s}"));
+        let fraction = if let Some(percent) = s.strip_suffix('%') {
+            percent.trim().parse::<f64>().map_err(|_| invalid())? / 100.0
+        } else if s.contains('.') {
+            s.parse::<f64>().map_err(|_| invalid())?
+        } else {
+            return s.parse().map(Self::Count).map_err(|_| invalid());
+        };
+        if (0.0..=1.0).contains(&fraction) {
+            Ok(Self::Fraction(fraction))
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+impl fmt::Display for Frequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Count(count) => write!(f, "{count}"),
+            Self::Fraction(fraction) => write!(f, "{}%", fraction * 100.0),
+        }
+    }
+}
+
+/// Which keywords are dropped from the alphabet before searching and binning
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct VocabularyFilter {
+    /// Regexes. A keyword matching any of them is dropped, e.g. `^\d+$`, or `^.{25,}$` for OCR noise
+    pub deny: Vec<String>,
+    /// Keywords in fewer documents are dropped, e.g. 2 to drop hapaxes
+    pub min_df: Option<Frequency>,
+    /// Keywords in more documents are dropped
+    pub max_df: Option<Frequency>,
+    /// Phrase keywords in fewer documents are dropped. Single words are left alone
+    pub min_phrase_df: Option<Frequency>,
+    /// Phrase keywords whose words co-occur less than this, as `Vocabulary::pmi`, are dropped. 0 keeps phrases
+    /// whose words appear together more often than chance
+    pub min_pmi: Option<f64>,
+    /// Drops the N keywords in the most documents, as corpus-specific stopwords
+    pub drop_top_n: Option<usize>,
+    /// Keeps at most this many keywords, the ones in the most documents
+    pub max_keywords: Option<usize>,
+}
+
+impl VocabularyFilter {
+    /// Whether any filter is set
+    pub fn is_active(&self) -> bool {
+        self != &Self::default()
+    }
+}
+
+/// How many keywords each filter removed, in the order the filters run
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PruningReport {
+    /// Keywords before pruning
+    pub keywords: usize,
+    /// Matched the deny-list
+    pub denied: usize,
+    /// Below the minimum document frequency
+    pub below_min_df: usize,
+    /// Above the maximum document frequency
+    pub above_max_df: usize,
+    /// Phrases below the minimum phrase document frequency or PMI
+    pub weak_phrases: usize,
+    /// Among the most frequent keywords dropped by `drop_top_n`
+    pub most_frequent: usize,
+    /// Over `max_keywords`
+    pub over_cap: usize,
+    /// Keywords left
+    pub kept: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prunes_in_order() {
+        let documents = [
+            "the cat sat",
+            "the cat ran",
+            "the dog sat",
+            "the dog ran 1999",
+            "the bird xxxxx",
+        ];
+        let mut vocabulary = Vocabulary::default();
+        for document in documents {
+            let tokens: Vec<String> = document.split(' ').map(str::to_string).collect();
+            vocabulary.add_document(&tokens);
+        }
+        assert_eq!(vocabulary.num_documents(), 5);
+        assert_eq!(vocabulary.document_frequency("the"), 5);
+        assert_eq!(vocabulary.document_frequency("sat"), 2);
+
+        let (alphabet, report) = vocabulary.prune(&VocabularyFilter::default()).unwrap();
+        assert_eq!(alphabet, vocabulary.alphabet());
+        assert_eq!(report.kept, 8);
+
+        let filter = VocabularyFilter {
+            deny: vec![r"^\d+$".to_string(), r"^.{5,}$".to_string()],
+            min_df: Some(Frequency::Count(2)),
+            max_df: Some("90%".parse().unwrap()),
+            min_phrase_df: None,
+            min_pmi: None,
+            drop_top_n: None,
+            max_keywords: Some(3),
+        };
+        let (alphabet, report) = vocabulary.prune(&filter).unwrap();
+        // cat, dog, sat and ran are in 2 documents each, so the cap keeps the first three alphabetically
+        assert_eq!(alphabet, HashSet::from(["cat", "dog", "ran"].map(str::to_string)));
+        assert_eq!(
+            report,
+            PruningReport {
+                keywords: 8,
+                denied: 2,
+                below_min_df: 1,
+                above_max_df: 1,
+                weak_phrases: 0,
+                most_frequent: 0,
+                over_cap: 1,
+                kept: 3,
+            }
+        );
+
+        let stopwords = VocabularyFilter {
+            drop_top_n: Some(1),
+            ..VocabularyFilter::default()
+        };
+        assert!(!vocabulary.prune(&stopwords).unwrap().0.contains("the"));
+
+        // "the" is in every document, so "the+cat" co-occurs exactly as often as chance predicts, while "cat" and
+        // "sat" share fewer documents than chance
+        let mut phrases = Vocabulary::default();
+        for document in ["the cat sat", "the cat ran", "the dog sat"] {
+            let words: Vec<&str> = document.split(' ').collect();
+            let tokens: Vec<String> = words
+                .iter()
+                .map(|word| word.to_string())
+                .chain(words.windows(2).map(|pair| pair.join("+")))
+                .collect();
+            phrases.add_document(&tokens);
+        }
+        assert!(phrases.pmi("the+cat").abs() < 1e-9);
+        assert!(phrases.pmi("cat+sat") < 0.0);
+        let strong = VocabularyFilter {
+            min_phrase_df: Some(Frequency::Count(2)),
+            min_pmi: Some(0.0),
+            ..VocabularyFilter::default()
+        };
+        let (alphabet, report) = phrases.prune(&strong).unwrap();
+        assert!(alphabet.contains("the+cat") && alphabet.contains("sat"));
+        assert_eq!(report.weak_phrases, 4);
+
+        assert_eq!("0.5".parse::<Frequency>().unwrap(), Frequency::Fraction(0.5));
+        assert_eq!("3".parse::<Frequency>().unwrap(), Frequency::Count(3));
+        assert!("150%".parse::<Frequency>().is_err());
+        assert!(vocabulary.prune(&VocabularyFilter { deny: vec!["(".to_string()], ..VocabularyFilter::default() }).is_err());
+    }
+}